@@ -0,0 +1,406 @@
+use crate::backend::StorageBackend;
+use crate::date::DateTime;
+use actix_web::web::{self, Bytes};
+use async_zip::base::write::ZipFileWriter;
+use async_zip::{ZipDateTime, ZipDateTimeBuilder, ZipEntryBuilder};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::channel::mpsc;
+use futures::io::AsyncWrite;
+use futures::{ready, AsyncWriteExt, SinkExt, Stream, StreamExt};
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Chunks buffered between the archive writer and the response.
+const CHANNEL_SIZE: usize = 4;
+const TAR_BLOCK_SIZE: u64 = 512;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Zip,
+    TarGz,
+}
+
+impl Format {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "zip" => Some(Format::Zip),
+            "tar.gz" => Some(Format::TarGz),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Zip => "application/zip",
+            Format::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// A file put into an archive.
+#[derive(Clone)]
+pub struct Entry {
+    /// The path inside the archive.
+    pub name: String,
+    /// The key of the content in the storage backend.
+    pub key: String,
+    pub size: u64,
+    /// Unix timestamp of the last modification.
+    pub modified: u64,
+}
+
+/// The length of the archive, known up front for zip as its entries are stored uncompressed.
+pub fn length(format: Format, entries: &[Entry]) -> Option<u64> {
+    match format {
+        Format::Zip => Some(zip_length(entries)),
+        Format::TarGz => None,
+    }
+}
+
+/// The layout of [`write_zip`]: each entry gets a local header with a ZIP64 field holding both sizes,
+/// its content and a data descriptor, its central directory header has a ZIP64 field with the offset too.
+fn zip_length(entries: &[Entry]) -> u64 {
+    if entries.is_empty() {
+        return 22;
+    }
+
+    let files: u64 = entries
+        .iter()
+        .map(|entry| (30 + 20) + entry.name.len() as u64 + entry.size + 16)
+        .sum();
+    let central_directory: u64 = entries
+        .iter()
+        .map(|entry| (46 + 28) + entry.name.len() as u64)
+        .sum();
+    // ZIP64 end of central directory record and locator, then the end of central directory record.
+    files + central_directory + 56 + 20 + 22
+}
+
+/// Streams the archive of `entries`, reading one file after the other from the backend.
+pub fn stream(
+    backend: Arc<dyn StorageBackend>,
+    format: Format,
+    entries: Vec<Entry>,
+) -> impl Stream<Item = io::Result<Bytes>> {
+    let (mut sender, receiver) = mpsc::channel(CHANNEL_SIZE);
+    actix_web::rt::spawn(async move {
+        let result = match format {
+            Format::Zip => write_zip(backend.as_ref(), &entries, &mut sender).await,
+            Format::TarGz => write_tar_gz(backend.as_ref(), &entries, &mut sender).await,
+        };
+        if let Err(err) = result {
+            // Fails if the client is already gone, which is what usually ended the writing.
+            let _ = sender.send(Err(err)).await;
+        }
+    });
+
+    receiver
+}
+
+type Sender = mpsc::Sender<io::Result<Bytes>>;
+
+fn disconnected<E>(_: E) -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "Client disconnected")
+}
+
+/// Fails if fewer or more bytes than the announced size were read, the archive headers would be wrong.
+fn check_size(entry: &Entry, read: u64) -> io::Result<()> {
+    if read == entry.size {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("{} changed while archiving it", entry.key),
+        ))
+    }
+}
+
+/// Writes into the response, for the zip writer.
+struct ResponseWriter<'a>(&'a mut Sender);
+
+impl AsyncWrite for ResponseWriter<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let sender = &mut self.get_mut().0;
+        ready!(sender.poll_ready(cx)).map_err(disconnected)?;
+        sender
+            .start_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(disconnected)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The modification time of a zip entry, clamped to the years MS-DOS dates can hold.
+fn zip_date_time(timestamp: u64) -> ZipDateTime {
+    let date = DateTime::from_timestamp(timestamp);
+    if date.year < 1980 {
+        return ZipDateTimeBuilder::new().year(1980).month(1).day(1).build();
+    }
+
+    ZipDateTimeBuilder::new()
+        .year(date.year.min(2107) as i32)
+        .month(date.month)
+        .day(date.day)
+        .hour(date.hour)
+        .minute(date.minute)
+        .second(date.second)
+        .build()
+}
+
+/// Stored without compression, streamed entries always get a ZIP64 field, so there is no size limit.
+async fn write_zip(
+    backend: &dyn StorageBackend,
+    entries: &[Entry],
+    sender: &mut Sender,
+) -> io::Result<()> {
+    let mut zip = ZipFileWriter::new(ResponseWriter(sender));
+    for entry in entries {
+        let builder =
+            ZipEntryBuilder::new(entry.name.clone().into(), async_zip::Compression::Stored)
+                .last_modification_date(zip_date_time(entry.modified))
+                .unix_permissions(0o644);
+        let mut writer = zip
+            .write_entry_stream(builder)
+            .await
+            .map_err(io::Error::other)?;
+
+        let mut body = backend.open(&entry.key, None).await?.body;
+        let mut read = 0;
+        while let Some(bytes) = body.next().await {
+            let bytes = bytes?;
+            read += bytes.len() as u64;
+            writer.write_all(&bytes).await?;
+        }
+        check_size(entry, read)?;
+        writer.close().await.map_err(io::Error::other)?;
+    }
+
+    zip.close().await.map_err(io::Error::other)?;
+    Ok(())
+}
+
+/// Sends what was compressed so far, the encoder keeps the rest until there is enough for a block.
+async fn send_compressed(compressed: Vec<u8>, sender: &mut Sender) -> io::Result<()> {
+    if !compressed.is_empty() {
+        sender
+            .send(Ok(Bytes::from(compressed)))
+            .await
+            .map_err(disconnected)?;
+    }
+    Ok(())
+}
+
+type TarGz = tar::Builder<GzEncoder<Vec<u8>>>;
+
+/// Runs `write` on a blocking thread, compressing would hold up the actor thread, then sends what was compressed.
+async fn write_blocking<F>(builder: TarGz, sender: &mut Sender, write: F) -> io::Result<TarGz>
+where
+    F: FnOnce(&mut TarGz) -> io::Result<()> + Send + 'static,
+{
+    let (builder, compressed) = web::block(move || {
+        let mut builder = builder;
+        write(&mut builder)?;
+        let compressed = std::mem::take(builder.get_mut().get_mut());
+        Ok::<_, io::Error>((builder, compressed))
+    })
+    .await
+    .map_err(io::Error::other)??;
+    send_compressed(compressed, sender).await?;
+    Ok(builder)
+}
+
+/// Compresses chunk by chunk on blocking threads, long names and huge files get GNU extension headers.
+async fn write_tar_gz(
+    backend: &dyn StorageBackend,
+    entries: &[Entry],
+    sender: &mut Sender,
+) -> io::Result<()> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::fast()));
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(entry.size);
+        header.set_mode(0o644);
+        header.set_mtime(entry.modified);
+        let name = entry.name.clone();
+        // Only writes the headers, the content follows as it is read from the backend.
+        builder = write_blocking(builder, sender, move |builder| {
+            builder.append_data(&mut header, &name, io::empty())
+        })
+        .await?;
+
+        let mut body = backend.open(&entry.key, None).await?.body;
+        let mut read = 0;
+        while let Some(bytes) = body.next().await {
+            let bytes = bytes?;
+            read += bytes.len() as u64;
+            builder = write_blocking(builder, sender, move |builder| {
+                builder.get_mut().write_all(&bytes)
+            })
+            .await?;
+        }
+        check_size(entry, read)?;
+        let padding = (TAR_BLOCK_SIZE - read % TAR_BLOCK_SIZE) % TAR_BLOCK_SIZE;
+        builder = write_blocking(builder, sender, move |builder| {
+            builder
+                .get_mut()
+                .write_all(&[0; TAR_BLOCK_SIZE as usize][..padding as usize])
+        })
+        .await?;
+    }
+
+    let compressed = web::block(move || builder.into_inner()?.finish())
+        .await
+        .map_err(io::Error::other)??;
+    send_compressed(compressed, sender).await
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{length, stream, zip_date_time, Entry, Format};
+    use crate::backend::{MemoryBackend, StorageBackend};
+    use actix_web::web::Bytes;
+    use async_zip::base::read::mem::ZipFileReader;
+    use flate2::read::GzDecoder;
+    use futures::{AsyncReadExt, TryStreamExt};
+    use std::io::Read;
+    use std::sync::Arc;
+
+    async fn entries(backend: &dyn StorageBackend) -> Vec<(Entry, String)> {
+        backend.create_dir("user").await.unwrap();
+        backend.create_dir("user/bucket").await.unwrap();
+        let mut entries = Vec::new();
+        for (name, content) in [
+            ("bucket/hello.txt".to_string(), "hello world".to_string()),
+            (
+                format!("bucket/{}.txt", "x".repeat(120)),
+                "long".repeat(1000),
+            ),
+            ("bucket/ümlaut.txt".to_string(), String::new()),
+        ] {
+            let key = format!("user/{}", name);
+            let mut writer = backend.create(&key).await.unwrap();
+            writer.write(Bytes::from(content.clone())).await.unwrap();
+            writer.finish().await.unwrap();
+            entries.push((
+                Entry {
+                    name,
+                    key,
+                    size: content.len() as u64,
+                    modified: 1700000000,
+                },
+                content,
+            ));
+        }
+        entries
+    }
+
+    async fn archive(format: Format) -> (Vec<u8>, Vec<(Entry, String)>) {
+        let backend = Arc::new(MemoryBackend::default());
+        let entries = entries(backend.as_ref()).await;
+        let archive: Vec<Bytes> = stream(
+            backend,
+            format,
+            entries.iter().map(|(entry, _)| entry.clone()).collect(),
+        )
+        .try_collect()
+        .await
+        .unwrap();
+        (archive.concat(), entries)
+    }
+
+    #[test]
+    fn test_zip_date_time() {
+        // 2023-11-14 22:13:20 UTC
+        let date = zip_date_time(1700000000);
+        assert_eq!((date.year(), date.month(), date.day()), (2023, 11, 14));
+        assert_eq!((date.hour(), date.minute(), date.second()), (22, 13, 20));
+        assert_eq!(zip_date_time(0).year(), 1980);
+    }
+
+    #[actix_web::test]
+    async fn test_zip() {
+        let (zip, entries) = archive(Format::Zip).await;
+        let archived: Vec<Entry> = entries.iter().map(|(entry, _)| entry.clone()).collect();
+        assert_eq!(length(Format::Zip, &archived), Some(zip.len() as u64));
+        assert_eq!(length(Format::Zip, &[]), Some(22));
+        let reader = ZipFileReader::new(zip).await.unwrap();
+        assert_eq!(reader.file().entries().len(), entries.len());
+        for (index, (entry, content)) in entries.iter().enumerate() {
+            let mut file = reader.reader_with_entry(index).await.unwrap();
+            assert_eq!(
+                file.entry().filename().as_str().unwrap(),
+                entry.name.as_str()
+            );
+            let mut read = String::new();
+            file.read_to_string(&mut read).await.unwrap();
+            assert_eq!(&read, content);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_zip_length() {
+        let backend = Arc::new(MemoryBackend::default());
+        let entries: Vec<Entry> = entries(backend.as_ref())
+            .await
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .collect();
+        let empty = entries[2].clone();
+        // The length follows the layout async_zip writes, which an update of it could change.
+        for archived in [
+            Vec::new(),
+            vec![empty.clone()],
+            vec![entries[0].clone()],
+            vec![empty.clone(), empty],
+            entries,
+        ] {
+            let zip: Vec<Bytes> = stream(backend.clone(), Format::Zip, archived.clone())
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(
+                length(Format::Zip, &archived),
+                Some(zip.concat().len() as u64)
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_tar_gz() {
+        let (tar_gz, entries) = archive(Format::TarGz).await;
+        let archived: Vec<Entry> = entries.iter().map(|(entry, _)| entry.clone()).collect();
+        assert_eq!(length(Format::TarGz, &archived), None);
+        let mut tar = tar::Archive::new(GzDecoder::new(&tar_gz[..]));
+        let mut files = tar.entries().unwrap();
+        for (entry, content) in &entries {
+            let mut file = files.next().unwrap().unwrap();
+            assert_eq!(file.path().unwrap().to_str().unwrap(), entry.name);
+            assert_eq!(file.header().mtime().unwrap(), entry.modified);
+            let mut read = String::new();
+            file.read_to_string(&mut read).unwrap();
+            assert_eq!(&read, content);
+        }
+        assert!(files.next().is_none());
+    }
+}
@@ -0,0 +1,340 @@
+use crate::backend::{Metadata, Object, ObjectWriter, StorageBackend};
+use actix_web::web;
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures::StreamExt;
+use rand::Rng;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+/// Files are written here first and moved to their key once complete.
+const PARTIAL_DIR: &str = ".partial";
+/// Partial files untouched for longer were left behind by a crash, newer ones may still be written by another
+/// instance sharing the storage.
+const STALE_PARTIAL_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub struct FilesystemBackend {
+    root: PathBuf,
+}
+
+impl FilesystemBackend {
+    /// Opens the storage at `root`, removing stale partial files left behind by a previous run.
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref().to_path_buf();
+        fs::create_dir(&root).unwrap_or(());
+
+        let partial = root.join(PARTIAL_DIR);
+        let stale = fs::read_dir(&partial)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| {
+                        entry
+                            .metadata()
+                            .and_then(|metadata| metadata.modified())
+                            .ok()
+                            .and_then(|modified| modified.elapsed().ok())
+                            .is_some_and(|age| age > STALE_PARTIAL_AGE)
+                    })
+                    .filter(|entry| fs::remove_file(entry.path()).is_ok())
+                    .count()
+            })
+            .unwrap_or(0);
+        if stale > 0 {
+            println!("Removed {} stale partial uploads", stale);
+        }
+        fs::create_dir(&partial).unwrap_or(());
+
+        FilesystemBackend { root }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+/// A write failed before, the file is gone with it.
+fn failed() -> io::Error {
+    io::Error::other("Writer already failed")
+}
+
+async fn block<F, R>(f: F) -> io::Result<R>
+where
+    F: FnOnce() -> io::Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    web::block(f).await.map_err(io::Error::other)?
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for FilesystemBackend {
+    async fn create_dir(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+        block(move || match fs::create_dir(&path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+            result => result,
+        })
+        .await
+    }
+
+    async fn create_new_dir(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+        block(move || fs::create_dir(&path)).await
+    }
+
+    async fn remove_dir(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+        block(move || fs::remove_dir(&path)).await
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        let path = self.path(key);
+        block(move || Ok(path.exists())).await
+    }
+
+    async fn list(&self, key: &str) -> io::Result<Vec<String>> {
+        let path = self.path(key);
+        block(move || {
+            fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+                .collect()
+        })
+        .await
+    }
+
+    async fn create(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>> {
+        let path = self.path(key);
+        let partial = self.root.join(PARTIAL_DIR).join(
+            rand::thread_rng()
+                .sample_iter(rand::distributions::Alphanumeric)
+                .take(32)
+                .map(char::from)
+                .collect::<String>(),
+        );
+        let (file, path, partial) = block(move || {
+            if path.exists() {
+                return Err(io::ErrorKind::AlreadyExists.into());
+            }
+            if !path.parent().map(Path::is_dir).unwrap_or(false) {
+                return Err(io::ErrorKind::NotFound.into());
+            }
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&partial)?;
+
+            Ok((file, path, partial))
+        })
+        .await?;
+
+        Ok(Box::new(FileWriter {
+            file: Some(file),
+            path,
+            partial,
+        }))
+    }
+
+    async fn append(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>> {
+        let path = self.path(key);
+        let file = block(move || OpenOptions::new().append(true).create(true).open(&path)).await?;
+
+        Ok(Box::new(AppendWriter { file: Some(file) }))
+    }
+
+    async fn metadata(&self, key: &str) -> io::Result<Metadata> {
+        let path = self.path(key);
+        let metadata = block(move || fs::metadata(&path)).await?;
+
+        Ok(Metadata {
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    async fn open(&self, key: &str, range: Option<Range<u64>>) -> io::Result<Object> {
+        let path = self.path(key);
+        let (file, size) = block(move || {
+            let mut file = File::open(&path)?;
+            let len = file.metadata()?.len();
+            let range = range.unwrap_or(0..len);
+            file.seek(SeekFrom::Start(range.start))?;
+
+            Ok((file, range.end.min(len).saturating_sub(range.start)))
+        })
+        .await?;
+
+        let body = futures::stream::try_unfold((file, size), |(mut file, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+
+            let (file, chunk) = block(move || {
+                let mut chunk = vec![0; READ_CHUNK_SIZE.min(remaining as usize)];
+                let read = file.read(&mut chunk)?;
+                chunk.truncate(read);
+                Ok((file, chunk))
+            })
+            .await?;
+
+            if chunk.is_empty() {
+                Ok(None)
+            } else {
+                let remaining = remaining - chunk.len() as u64;
+                Ok(Some((Bytes::from(chunk), (file, remaining))))
+            }
+        });
+
+        Ok(Object {
+            size,
+            body: body.boxed_local(),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+        block(move || fs::remove_file(&path)).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let from = self.path(from);
+        let to = self.path(to);
+        block(move || {
+            fs::hard_link(&from, &to)?;
+            fs::remove_file(&from)
+        })
+        .await
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.path(key))
+    }
+}
+
+struct FileWriter {
+    file: Option<File>,
+    path: PathBuf,
+    /// Where the content is written to until the writer is finished.
+    partial: PathBuf,
+}
+
+#[async_trait(?Send)]
+impl ObjectWriter for FileWriter {
+    async fn write(&mut self, chunk: Bytes) -> io::Result<()> {
+        let mut file = self.file.take().ok_or_else(failed)?;
+        self.file = Some(block(move || file.write_all(&chunk).map(|_| file)).await?);
+
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let mut file = self.file.take().ok_or_else(failed)?;
+        let path = self.path.clone();
+        let partial = self.partial.clone();
+        block(move || {
+            file.flush()?;
+            file.sync_all()?;
+            // Unlike a rename, linking fails instead of replacing a file created in the meantime.
+            fs::hard_link(&partial, &path)?;
+            fs::remove_file(&partial)?;
+            if let Some(parent) = path.parent() {
+                File::open(parent)
+                    .and_then(|parent| parent.sync_all())
+                    .unwrap_or(());
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl Drop for FileWriter {
+    /// Removes the partial file if the writer is dropped before it is finished.
+    fn drop(&mut self) {
+        self.file.take();
+        fs::remove_file(&self.partial).unwrap_or(());
+    }
+}
+
+/// Writes to the end of the file in place, what was written stays there.
+struct AppendWriter {
+    file: Option<File>,
+}
+
+#[async_trait(?Send)]
+impl ObjectWriter for AppendWriter {
+    async fn write(&mut self, chunk: Bytes) -> io::Result<()> {
+        let mut file = self.file.take().ok_or_else(failed)?;
+        self.file = Some(block(move || file.write_all(&chunk).map(|_| file)).await?);
+
+        Ok(())
+    }
+
+    async fn finish(mut self: Box<Self>) -> io::Result<()> {
+        let mut file = self.file.take().ok_or_else(failed)?;
+        block(move || {
+            file.flush()?;
+            file.sync_all()
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::filesystem::STALE_PARTIAL_AGE;
+    use crate::backend::{FilesystemBackend, StorageBackend};
+    use actix_web::web::Bytes;
+    use std::fs;
+    use std::time::SystemTime;
+
+    #[actix_web::test]
+    async fn partial_files() {
+        let root = std::env::temp_dir().join("shares-test-partial");
+        let backend = FilesystemBackend::new(&root);
+        backend.create_dir("user").await.unwrap();
+
+        let mut writer = backend.create("user/file.txt").await.unwrap();
+        writer.write(Bytes::from_static(b"This is ")).await.unwrap();
+        assert!(!backend.exists("user/file.txt").await.unwrap());
+        assert_eq!(fs::read_dir(root.join(".partial")).unwrap().count(), 1);
+        drop(writer);
+        assert_eq!(fs::read_dir(root.join(".partial")).unwrap().count(), 0);
+
+        let mut writer = backend.create("user/file.txt").await.unwrap();
+        let mut other = backend.create("user/file.txt").await.unwrap();
+        writer.write(Bytes::from_static(b"This is ")).await.unwrap();
+        other.write(Bytes::from_static(b"Other")).await.unwrap();
+        writer.finish().await.unwrap();
+        assert!(other.finish().await.is_err());
+        assert_eq!(
+            fs::read(root.join("user/file.txt")).unwrap(),
+            b"This is ".to_vec()
+        );
+        assert_eq!(fs::read_dir(root.join(".partial")).unwrap().count(), 0);
+
+        fs::write(root.join(".partial/stale"), b"stale").unwrap();
+        fs::File::options()
+            .write(true)
+            .open(root.join(".partial/stale"))
+            .unwrap()
+            .set_modified(SystemTime::now() - STALE_PARTIAL_AGE * 2)
+            .unwrap();
+        // Possibly written by another instance.
+        fs::write(root.join(".partial/recent"), b"recent").unwrap();
+        FilesystemBackend::new(&root);
+        let partial: Vec<_> = fs::read_dir(root.join(".partial"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(partial, ["recent"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
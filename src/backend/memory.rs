@@ -0,0 +1,210 @@
+use crate::backend::{Metadata, Object, ObjectWriter, StorageBackend};
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Keeps all files in memory, nothing survives a restart. Meant for tests.
+#[derive(Default)]
+pub struct MemoryBackend {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    dirs: BTreeSet<String>,
+    files: BTreeMap<String, MemoryFile>,
+}
+
+struct MemoryFile {
+    content: Vec<u8>,
+    modified: SystemTime,
+}
+
+impl MemoryState {
+    fn exists(&self, key: &str) -> bool {
+        key.is_empty() || self.dirs.contains(key) || self.files.contains_key(key)
+    }
+
+    fn children<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.dirs
+            .iter()
+            .chain(self.files.keys())
+            .filter(move |child| parent(child) == key)
+            .map(|child| name(child))
+    }
+
+    fn check_parent(&self, key: &str) -> io::Result<()> {
+        if self.dirs.contains(parent(key)) || parent(key).is_empty() {
+            Ok(())
+        } else {
+            Err(io::ErrorKind::NotFound.into())
+        }
+    }
+}
+
+fn parent(key: &str) -> &str {
+    key.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("")
+}
+
+fn name(key: &str) -> &str {
+    key.rsplit_once('/').map(|(_, name)| name).unwrap_or(key)
+}
+
+#[async_trait(?Send)]
+impl StorageBackend for MemoryBackend {
+    async fn create_dir(&self, key: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.files.contains_key(key) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        state.check_parent(key)?;
+        state.dirs.insert(key.to_string());
+
+        Ok(())
+    }
+
+    async fn create_new_dir(&self, key: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.exists(key) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        state.check_parent(key)?;
+        state.dirs.insert(key.to_string());
+
+        Ok(())
+    }
+
+    async fn remove_dir(&self, key: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if !state.dirs.contains(key) {
+            Err(io::ErrorKind::NotFound.into())
+        } else if state.children(key).next().is_some() {
+            Err(io::Error::other("Directory not empty"))
+        } else {
+            state.dirs.remove(key);
+            Ok(())
+        }
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(self.state.lock().unwrap().exists(key))
+    }
+
+    async fn list(&self, key: &str) -> io::Result<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        if state.dirs.contains(key) || key.is_empty() {
+            Ok(state.children(key).map(String::from).collect())
+        } else {
+            Err(io::ErrorKind::NotFound.into())
+        }
+    }
+
+    async fn create(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>> {
+        let state = self.state.lock().unwrap();
+        if state.exists(key) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        state.check_parent(key)?;
+
+        Ok(Box::new(MemoryWriter {
+            state: self.state.clone(),
+            key: key.to_string(),
+            content: Vec::new(),
+        }))
+    }
+
+    async fn metadata(&self, key: &str) -> io::Result<Metadata> {
+        let state = self.state.lock().unwrap();
+        let file = state
+            .files
+            .get(key)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+
+        Ok(Metadata {
+            size: file.content.len() as u64,
+            modified: file.modified,
+        })
+    }
+
+    async fn open(&self, key: &str, range: Option<Range<u64>>) -> io::Result<Object> {
+        let state = self.state.lock().unwrap();
+        let content = &state
+            .files
+            .get(key)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?
+            .content;
+        let range = range.unwrap_or(0..content.len() as u64);
+        let content = content
+            .get(range.start as usize..(range.end as usize).min(content.len()))
+            .unwrap_or_default();
+
+        Ok(Object {
+            size: content.len() as u64,
+            body: futures::stream::once(futures::future::ok(Bytes::copy_from_slice(content)))
+                .boxed_local(),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        self.state
+            .lock()
+            .unwrap()
+            .files
+            .remove(key)
+            .map(|_| ())
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.exists(to) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        state.check_parent(to)?;
+        let file = state
+            .files
+            .remove(from)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        state.files.insert(to.to_string(), file);
+
+        Ok(())
+    }
+}
+
+/// Keeps the content to itself until finished, so partial files are never visible.
+struct MemoryWriter {
+    state: Arc<Mutex<MemoryState>>,
+    key: String,
+    content: Vec<u8>,
+}
+
+#[async_trait(?Send)]
+impl ObjectWriter for MemoryWriter {
+    async fn write(&mut self, chunk: Bytes) -> io::Result<()> {
+        self.content.extend_from_slice(&chunk);
+
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.exists(&self.key) {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        state.check_parent(&self.key)?;
+        state.files.insert(
+            self.key,
+            MemoryFile {
+                content: self.content,
+                modified: SystemTime::now(),
+            },
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+mod filesystem;
+mod memory;
+mod s3;
+
+pub use self::filesystem::FilesystemBackend;
+pub use self::memory::MemoryBackend;
+pub use self::s3::S3Backend;
+
+use crate::config::{BackendKind, Config};
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures::stream::LocalBoxStream;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub struct Metadata {
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// A file (or a byte range of it) opened for reading, streamed back in chunks.
+pub struct Object {
+    /// The number of bytes in `body`.
+    pub size: u64,
+    pub body: LocalBoxStream<'static, io::Result<Bytes>>,
+}
+
+/// A file opened for writing, filled chunk by chunk.
+///
+/// The file only appears once the writer is finished, dropping it before discards the content.
+#[async_trait(?Send)]
+pub trait ObjectWriter {
+    async fn write(&mut self, chunk: Bytes) -> io::Result<()>;
+
+    async fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// Storage for the `user folder / bucket / filename` hierarchy.
+///
+/// Keys are `/` separated paths relative to the storage root, directories are
+/// the user folders and buckets, files are the uploads inside a bucket.
+#[async_trait(?Send)]
+pub trait StorageBackend: Send + Sync {
+    /// Creates the directory at `key`, succeeding if it already exists.
+    async fn create_dir(&self, key: &str) -> io::Result<()>;
+
+    /// Creates the directory at `key`, failing with `AlreadyExists` if it already exists.
+    ///
+    /// Of concurrent calls for the same `key` only one succeeds.
+    async fn create_new_dir(&self, key: &str) -> io::Result<()>;
+
+    /// Removes the directory at `key`, failing if it is not empty.
+    async fn remove_dir(&self, key: &str) -> io::Result<()>;
+
+    async fn exists(&self, key: &str) -> io::Result<bool>;
+
+    /// Lists the names of all entries directly inside the directory at `key`.
+    async fn list(&self, key: &str) -> io::Result<Vec<String>>;
+
+    /// Creates a new file at `key`, failing if it already exists.
+    async fn create(&self, key: &str) -> io::Result<Box<dyn ObjectWriter>>;
+
+    /// Opens the file at `key` for appending, creating it if needed. Unlike with `create` everything written is kept,
+    /// even if the writer is never finished. Fails with `Unsupported` if the backend can not append to files.
+    async fn append(&self, _key: &str) -> io::Result<Box<dyn ObjectWriter>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    async fn metadata(&self, key: &str) -> io::Result<Metadata>;
+
+    /// Opens the file at `key`, limited to the bytes in `range` if given.
+    async fn open(&self, key: &str, range: Option<Range<u64>>) -> io::Result<Object>;
+
+    async fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// Moves the file at `from` to `to`, failing if `to` already exists.
+    async fn rename(&self, from: &str, to: &str) -> io::Result<()>;
+
+    /// The path of `key` on the local disk, if the backend keeps files there.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Lowercase hex of `bytes`, like the SHA-256 digests naming blobs and signing S3 requests.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn open_backend(config: &Config) -> Arc<dyn StorageBackend> {
+    match config.backend {
+        BackendKind::Filesystem => Arc::new(FilesystemBackend::new(&config.storage_folder)),
+        BackendKind::Memory => Arc::new(MemoryBackend::default()),
+        BackendKind::S3 => {
+            Arc::new(S3Backend::new(config.s3.clone().expect(
+                "`Config::load` requires the `s3` section for the s3 backend.",
+            )))
+        }
+    }
+}
@@ -1,33 +1,416 @@
+use crate::keys::{self, IpRange};
+use crate::store::Usage;
+use actix_web::http::Uri;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Holds the secret of the key ids apart from the `config.yml`, so the hashes in there alone can not be tested against
+/// guesses. Every installation creates its own, it is never committed.
+const KEY_SECRET_FILE: &str = "key.secret";
+/// Usernames taken by fixed routes, e.g. `/s/{id}` would shadow the bucket pages of a user `s`.
+const RESERVED_USERS: &[&str] = &["api", "delete", "s", "tus"];
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct Config {
     pub host: String,
     pub port: i64,
+    /// The address the server is reachable at from the outside, e.g. `https://share.example.com`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
     pub storage_folder: String,
+    #[serde(default)]
+    pub backend: BackendKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3: Option<S3Config>,
+    /// Store identical uploads only once.
+    #[serde(default)]
+    pub dedup: bool,
+    /// Signs the deletion tokens, changing it invalidates all deletion links handed out.
+    #[serde(default)]
+    pub secret: String,
+    /// Derives the ids of hashed keys, read from `key.secret` instead of the `config.yml`.
+    #[serde(skip, default = "random_secret")]
+    pub key_secret: String,
+    /// Seconds between two runs of the reaper deleting expired files.
+    #[serde(default = "default_reaper_interval")]
+    pub reaper_interval: u64,
+    /// Largest upload in bytes, for users without their own `max_file_size`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    /// Bytes each user may store, for users without their own `quota_bytes`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+    /// Take the client address from the `proxy_header`, only enable this behind a proxy setting it.
+    #[serde(default)]
+    pub behind_proxy: bool,
+    /// The header the proxy sets, the other one is ignored as clients can send it too.
+    #[serde(default)]
+    pub proxy_header: ProxyHeader,
+    /// Further proxies in front of the one the server is behind, skipped when reading the forwarding headers.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_proxies: Vec<IpRange>,
+    /// Remove EXIF, XMP and IPTC metadata like GPS coordinates from uploaded JPEG, PNG and WebP images.
+    #[serde(default)]
+    pub strip_metadata: bool,
+    /// Largest width of a thumbnail in pixels.
+    #[serde(default = "default_thumbnail_size")]
+    pub thumbnail_width: u32,
+    /// Largest height of a thumbnail in pixels.
+    #[serde(default = "default_thumbnail_size")]
+    pub thumbnail_height: u32,
+    /// How the random names of new buckets are made.
+    #[serde(default)]
+    pub bucket_names: BucketNames,
+    /// Put all files of one upload into one bucket, for users without their own `group_uploads`.
+    #[serde(default)]
+    pub group_uploads: bool,
+    /// Seconds a resumable upload is kept, unfinished or not, after it was created.
+    #[serde(default = "default_tus_expiration")]
+    pub tus_expiration: u64,
+    /// Anyone may list and download whole buckets, for users without their own `public_listing`.
+    #[serde(default = "default_public_listing")]
+    pub public_listing: bool,
     pub users: HashMap<String, UserData>,
 }
 
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    #[default]
+    Filesystem,
+    Memory,
+    S3,
+}
+
+/// The header a proxy passes the client address in.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProxyHeader {
+    #[default]
+    XForwardedFor,
+    Forwarded,
+}
+
+/// Connection to an S3 compatible object storage, used by the `s3` backend.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://localhost:9000`
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prepended to every object key.
+    #[serde(default)]
+    pub prefix: String,
+    /// Address the bucket as `endpoint/bucket` instead of `bucket.endpoint`, needed for MinIO.
+    #[serde(default)]
+    pub path_style: bool,
+}
+
+impl S3Config {
+    /// The host and port of the `endpoint`, `None` if it is not an http or https url.
+    pub fn authority(&self) -> Option<String> {
+        let endpoint = self.endpoint.parse::<Uri>().ok()?;
+        if !matches!(endpoint.scheme_str(), Some("http" | "https")) {
+            return None;
+        }
+        endpoint.authority().map(|authority| authority.to_string())
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NameStyle {
+    /// Characters of the `alphabet`, letters and digits by default.
+    #[default]
+    Alphanumeric,
+    /// Random bytes in lowercase base32, `a-z` and `2-7`.
+    Base32,
+    /// Capitalized words like `BraveOrangeOtter`.
+    Words,
+}
+
+/// The random names of new buckets.
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct BucketNames {
+    #[serde(default)]
+    pub style: NameStyle,
+    /// Characters of a name, or words for the `words` style. Defaults to 16 characters or 3 words.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    /// The characters of `alphanumeric` names, letters and digits only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alphabet: Option<String>,
+    /// The words of the `words` style, the words of `word_list` are added on load.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<String>,
+    /// A file with one word per line, a dice roll before the word like in the EFF lists is ignored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub word_list: Option<String>,
+}
+
+impl BucketNames {
+    pub fn generate(&self) -> String {
+        let mut rng = rand::thread_rng();
+        match self.style {
+            NameStyle::Alphanumeric => {
+                let length = self.length.unwrap_or(16);
+                match &self.alphabet {
+                    Some(alphabet) => {
+                        let alphabet: Vec<char> = alphabet.chars().collect();
+                        (0..length)
+                            .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                            .collect()
+                    }
+                    None => (&mut rng)
+                        .sample_iter(rand::distributions::Alphanumeric)
+                        .take(length)
+                        .map(char::from)
+                        .collect(),
+                }
+            }
+            NameStyle::Base32 => {
+                let length = self.length.unwrap_or(16);
+                let bytes: Vec<u8> = (0..(length * 5).div_ceil(8)).map(|_| rng.gen()).collect();
+                let mut name = base32::encode(base32::Alphabet::RFC4648 { padding: false }, &bytes)
+                    .to_lowercase();
+                name.truncate(length);
+                name
+            }
+            NameStyle::Words => (0..self.length.unwrap_or(3))
+                .map(|_| {
+                    let word = &self.words[rng.gen_range(0..self.words.len())];
+                    let mut chars = word.chars();
+                    chars
+                        .next()
+                        .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                        .unwrap_or_default()
+                })
+                .collect(),
+        }
+    }
+
+    /// Reads the `word_list` and checks that only valid bucket names can be generated.
+    fn load(&mut self) -> Result<(), String> {
+        if let Some(word_list) = &self.word_list {
+            let content = fs::read_to_string(word_list)
+                .map_err(|err| format!("Could not read `word_list` {}: {}", word_list, err))?;
+            self.words.extend(
+                content
+                    .lines()
+                    .filter_map(|line| line.split_whitespace().last())
+                    .map(str::to_string),
+            );
+        }
+        // Words with other characters, like `drop-down`, can not be part of a name.
+        self.words
+            .retain(|word| !word.is_empty() && word.chars().all(|char| char.is_alphanumeric()));
+
+        if self.length == Some(0) {
+            return Err("`bucket_names.length` must be at least 1".to_string());
+        }
+        match self.style {
+            NameStyle::Alphanumeric
+                if self.alphabet.as_ref().is_some_and(|alphabet| {
+                    alphabet.is_empty() || !alphabet.chars().all(|char| char.is_alphanumeric())
+                }) =>
+            {
+                Err("`bucket_names.alphabet` must only contain letters and digits".to_string())
+            }
+            NameStyle::Words if self.words.is_empty() => Err(
+                "`bucket_names` needs `words` or a `word_list` for the `words` style".to_string(),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn default_reaper_interval() -> u64 {
+    300
+}
+
+fn default_thumbnail_size() -> u32 {
+    256
+}
+
+fn default_tus_expiration() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_public_listing() -> bool {
+    true
+}
+
+fn random_secret() -> String {
+    String::from_utf8(
+        rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(64)
+            .collect(),
+    )
+    .unwrap()
+}
+
+/// A new random key with 512 alphanumeric characters.
+pub fn random_key() -> String {
+    String::from_utf8(
+        rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(512)
+            .collect(),
+    )
+    .unwrap()
+}
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct UserData {
+    /// Hashed with `shares hash-key`, plain text keys are still accepted.
+    /// Grants every scope, leave it empty to only use `keys`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub key: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<ApiKey>,
     pub folder: String,
+    /// Seconds after which uploads expire, unless the upload sets its own `expires`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota_bytes: Option<u64>,
+    /// Overrides the global `strip_metadata` for this user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strip_metadata: Option<bool>,
+    /// Overrides the global `group_uploads` for this user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group_uploads: Option<bool>,
+    /// Overrides the global `public_listing` for this user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_listing: Option<bool>,
+    /// Shared by all clones, so every worker sees the same usage.
+    #[serde(skip)]
+    pub usage: Usage,
+}
+
+/// The upload limits of a user, `None` is unlimited.
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    pub max_file_size: Option<u64>,
+    pub quota_bytes: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Upload,
+    Delete,
+    List,
+    /// Grants every other scope.
+    Admin,
+}
+
+const ALL_SCOPES: &[Scope] = &[Scope::Admin];
+
+/// An additional key of a user, e.g. an upload only key for a CI job.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct ApiKey {
+    pub name: String,
+    /// Hashed with `shares hash-key`, like the user's `key`.
+    pub key: String,
+    pub scopes: Vec<Scope>,
+    /// Unix timestamp in seconds after which the key is rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    /// Addresses or ranges (e.g. `10.0.0.0/8`) the key may be used from, any if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_ips: Vec<IpRange>,
+}
+
+/// The key of a user a request authenticated with.
+#[derive(Clone, Copy)]
+pub struct MatchedKey<'a> {
+    pub name: &'a str,
+    pub scopes: &'a [Scope],
+    pub expires: Option<u64>,
+    pub allowed_ips: &'a [IpRange],
+}
+
+impl MatchedKey<'_> {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    /// Whether the key may be used at `now` (unix seconds) from `ip`.
+    pub fn is_usable(&self, now: u64, ip: Option<IpAddr>) -> bool {
+        let expired = self.expires.map(|expires| expires <= now).unwrap_or(false);
+        let allowed = self.allowed_ips.is_empty()
+            || ip
+                .map(|ip| self.allowed_ips.iter().any(|range| range.contains(ip)))
+                .unwrap_or(false);
+
+        !expired && allowed
+    }
+}
+
+impl UserData {
+    /// The user's `key` as `default`, followed by the named `keys`.
+    pub fn keys(&self) -> impl Iterator<Item = (&str, MatchedKey<'_>)> {
+        let default = Some(&self.key).filter(|key| !key.is_empty()).map(|key| {
+            (
+                key.as_str(),
+                MatchedKey {
+                    name: "default",
+                    scopes: ALL_SCOPES,
+                    expires: None,
+                    allowed_ips: &[],
+                },
+            )
+        });
+        default.into_iter().chain(self.keys.iter().map(|api_key| {
+            (
+                api_key.key.as_str(),
+                MatchedKey {
+                    name: &api_key.name,
+                    scopes: &api_key.scopes,
+                    expires: api_key.expires,
+                    allowed_ips: &api_key.allowed_ips,
+                },
+            )
+        }))
+    }
+
+    /// The key matching `key`, regardless of whether it is expired or restricted to other addresses.
+    pub fn find_key(&self, key_secret: &str, key: &str) -> Option<MatchedKey<'_>> {
+        self.find_candidate_key(key, &keys::key_id(key_secret, key))
+    }
+
+    /// Only checks the key with the id `id` (see [`keys::key_id`]) and plain text keys.
+    pub fn find_candidate_key(&self, key: &str, id: &str) -> Option<MatchedKey<'_>> {
+        self.keys()
+            .find(|(stored, _)| keys::is_candidate(stored, id) && keys::verify_key(stored, key))
+            .map(|(_, matched)| matched)
+    }
 }
 
 impl Default for UserData {
     fn default() -> Self {
         UserData {
-            key: String::from_utf8(
-                rand::thread_rng()
-                    .sample_iter(rand::distributions::Alphanumeric)
-                    .take(512)
-                    .collect(),
-            )
-            .unwrap(),
+            key: random_key(),
+            keys: Vec::new(),
             folder: "default_user".to_string(),
+            expire_after: None,
+            max_file_size: None,
+            quota_bytes: None,
+            strip_metadata: None,
+            group_uploads: None,
+            public_listing: None,
+            usage: Usage::default(),
         }
     }
 }
@@ -37,42 +420,307 @@ impl Default for Config {
         Config {
             host: "localhost".to_string(),
             port: 8080,
+            public_url: None,
             storage_folder: "store".to_string(),
+            backend: BackendKind::Filesystem,
+            s3: None,
+            dedup: false,
+            secret: random_secret(),
+            key_secret: random_secret(),
+            reaper_interval: default_reaper_interval(),
+            max_file_size: None,
+            quota_bytes: None,
+            behind_proxy: false,
+            proxy_header: ProxyHeader::default(),
+            trusted_proxies: Vec::new(),
+            strip_metadata: false,
+            thumbnail_width: default_thumbnail_size(),
+            thumbnail_height: default_thumbnail_size(),
+            bucket_names: BucketNames::default(),
+            group_uploads: false,
+            tus_expiration: default_tus_expiration(),
+            public_listing: default_public_listing(),
             users: HashMap::from([("default_user".to_string(), UserData::default())]),
         }
     }
 }
 
 impl Config {
+    /// The limits of `user_data`, falling back to the global ones.
+    pub fn limits(&self, user_data: &UserData) -> Limits {
+        Limits {
+            max_file_size: user_data.max_file_size.or(self.max_file_size),
+            quota_bytes: user_data.quota_bytes.or(self.quota_bytes),
+        }
+    }
+
+    /// Whether metadata is removed from the images `user_data` uploads.
+    pub fn strip_metadata(&self, user_data: &UserData) -> bool {
+        user_data.strip_metadata.unwrap_or(self.strip_metadata)
+    }
+
+    /// Whether all files of one upload of `user_data` go into one bucket.
+    pub fn group_uploads(&self, user_data: &UserData) -> bool {
+        user_data.group_uploads.unwrap_or(self.group_uploads)
+    }
+
+    /// Whether the buckets of `user_data` have a public index page and archive download.
+    pub fn public_listing(&self, user_data: &UserData) -> bool {
+        user_data.public_listing.unwrap_or(self.public_listing)
+    }
+
+    /// The configured `public_url` without a trailing slash, or `http://host:port`.
+    pub fn public_url(&self) -> String {
+        match &self.public_url {
+            Some(public_url) => public_url.trim_end_matches('/').to_string(),
+            None => format!("http://{}:{}", self.host, self.port),
+        }
+    }
+
     pub fn load() -> Result<Self, serde_yaml::Error> {
-        if let Ok(content) = fs::read_to_string("config.yml") {
-            serde_yaml::from_str(&*content)
+        Config::load_in(Path::new(""))
+    }
+
+    /// Loads the `config.yml` in `dir` and the `key.secret` next to it, creating them if needed.
+    fn load_in(dir: &Path) -> Result<Self, serde_yaml::Error> {
+        if let Ok(content) = fs::read_to_string(dir.join("config.yml")) {
+            let mut config: Config = serde_yaml::from_str(&content)?;
+            // A new `key.secret` would not match the ids of keys which are already hashed.
+            let hashed = config
+                .users
+                .values()
+                .any(|user_data| user_data.keys().any(|(stored, _)| keys::is_hashed(stored)));
+            config.key_secret = Config::load_key_secret_in(dir, !hashed)
+                .map_err(<serde_yaml::Error as serde::de::Error>::custom)?;
+            if config.secret.is_empty() {
+                eprintln!("Warning: No `secret` set in `config.yml`, deletion links will stop working on restart.");
+                config.secret = random_secret();
+            }
+            config
+                .bucket_names
+                .load()
+                .map_err(<serde_yaml::Error as serde::de::Error>::custom)?;
+            if config.backend == BackendKind::S3 {
+                let s3 = config.s3.as_ref().ok_or_else(|| {
+                    <serde_yaml::Error as serde::de::Error>::custom(
+                        "The `s3` section is required for the s3 backend",
+                    )
+                })?;
+                if s3.authority().is_none() {
+                    return Err(<serde_yaml::Error as serde::de::Error>::custom(format!(
+                        "`s3.endpoint` is not an http or https url: {}",
+                        s3.endpoint
+                    )));
+                }
+            }
+            if let Some(username) = config
+                .users
+                .keys()
+                .find(|username| RESERVED_USERS.contains(&username.as_str()))
+            {
+                return Err(<serde_yaml::Error as serde::de::Error>::custom(format!(
+                    "The username \"{}\" is reserved for other routes",
+                    username
+                )));
+            }
+            let mut plaintext_keys: Vec<(&String, &str)> = config
+                .users
+                .iter()
+                .flat_map(|(username, user_data)| {
+                    user_data
+                        .keys()
+                        .filter(|(stored, _)| !keys::is_hashed(stored))
+                        .map(move |(_, matched)| (username, matched.name))
+                })
+                .collect();
+            plaintext_keys.sort();
+            for (username, name) in plaintext_keys {
+                eprintln!("Warning: The key \"{}\" of user \"{}\" is stored in plain text, replace it with the output of `shares hash-key <key>`.", name, username);
+            }
+
+            Ok(config)
         } else {
-            let config = Config::default();
+            let mut config = Config {
+                key_secret: Config::load_key_secret_in(dir, true)
+                    .map_err(<serde_yaml::Error as serde::de::Error>::custom)?,
+                ..Config::default()
+            };
+            // Only the hash is written, the key is shown this one time.
+            for (username, user_data) in config.users.iter_mut() {
+                let key = std::mem::take(&mut user_data.key);
+                user_data.key = keys::hash_key(&config.key_secret, &key);
+                println!(
+                    "Created `config.yml`, the key of user \"{}\" is: {}",
+                    username, key
+                );
+            }
             let content = serde_yaml::to_string(&config).unwrap();
-            fs::write("config.yml", &*content)
+            fs::write(dir.join("config.yml"), &content)
                 .expect("Could not create or write to file `config.yml`.");
 
             Ok(config)
         }
     }
+
+    /// Reads the `key.secret` the ids of hashed keys are derived from, with `create` a missing one is created.
+    pub fn load_key_secret(create: bool) -> Result<String, String> {
+        Config::load_key_secret_in(Path::new(""), create)
+    }
+
+    fn load_key_secret_in(dir: &Path, create: bool) -> Result<String, String> {
+        let path = dir.join(KEY_SECRET_FILE);
+        match fs::read_to_string(&path) {
+            Ok(key_secret) if !key_secret.trim().is_empty() => Ok(key_secret.trim().to_string()),
+            Ok(_) => Err(format!("`{}` is empty", KEY_SECRET_FILE)),
+            Err(err) if err.kind() == ErrorKind::NotFound && create => {
+                let key_secret = random_secret();
+                fs::write(&path, &key_secret)
+                    .map_err(|err| format!("Could not write `{}`: {}", KEY_SECRET_FILE, err))?;
+                Ok(key_secret)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(format!(
+                "No `{}` found, start the server once to create one, or restore the one the keys were hashed with",
+                KEY_SECRET_FILE
+            )),
+            Err(err) => Err(format!("Could not read `{}`: {}", KEY_SECRET_FILE, err)),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::config::{BackendKind, BucketNames, NameStyle, Scope, UserData};
+    use crate::keys;
     use crate::Config;
 
     #[test]
     fn test_config_load() {
-        let config = Config::load().unwrap();
+        let dir = std::env::temp_dir().join("shares-test-config");
+        std::fs::create_dir_all(&dir).unwrap();
+        let key_secret = "testkeysecret";
+        std::fs::write(dir.join("key.secret"), format!("{}\n", key_secret)).unwrap();
+        let content = std::fs::read_to_string("config.yml").unwrap().replace(
+            "key: mysecret",
+            &format!("key: {}", keys::hash_key(key_secret, "mysecret")),
+        );
+        std::fs::write(dir.join("config.yml"), content).unwrap();
+
+        let config = Config::load_in(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
         assert_eq!(&config.host, "localhost");
         assert_eq!(config.port, 8080);
+        assert_eq!(config.public_url(), "http://localhost:8080");
         assert_eq!(&config.storage_folder, "store");
+        assert_eq!(config.backend, BackendKind::Filesystem);
+        assert_eq!(&config.secret, "mydeletionsecret");
+        assert_eq!(&config.key_secret, key_secret);
         assert_eq!(config.users.len(), 1);
 
-        let (user, user_data) = *(&config.users).iter().peekable().peek().unwrap();
+        let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
         assert_eq!(user, "user1");
         assert_eq!(&user_data.folder, "user1");
-        assert_eq!(&user_data.key, "mysecret");
+        assert!(keys::is_hashed(&user_data.key));
+        assert!(user_data.find_key(&config.key_secret, "mysecret").is_some());
+        assert!(user_data
+            .find_key(&config.key_secret, "mysecret2")
+            .is_none());
+    }
+
+    #[test]
+    fn test_reserved_users() {
+        let dir = std::env::temp_dir().join("shares-test-reserved");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("key.secret"), "testkeysecret").unwrap();
+        for (username, reserved) in [("api", true), ("s", true), ("user1", false)] {
+            std::fs::write(
+                dir.join("config.yml"),
+                format!("host: localhost\nport: 8080\nstorage_folder: store\nsecret: secret\nusers:\n  {}:\n    folder: user1\n    key: mysecret\n", username),
+            )
+            .unwrap();
+            assert_eq!(Config::load_in(&dir).is_err(), reserved, "{}", username);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_user_keys() {
+        let user_data: UserData = serde_yaml::from_str(
+            "folder: user1\nkeys:\n  - name: ci\n    key: cikey\n    scopes: [upload, list]\n    expires: 100\n    allowed_ips: [10.0.0.0/8]\n",
+        )
+        .unwrap();
+        assert!(user_data.key.is_empty());
+        assert_eq!(user_data.keys().count(), 1);
+        assert!(user_data.find_key("secret", "").is_none());
+
+        let api_key = user_data.find_key("secret", "cikey").unwrap();
+        assert_eq!(api_key.name, "ci");
+        assert!(api_key.has_scope(Scope::Upload));
+        assert!(!api_key.has_scope(Scope::Delete));
+        assert!(api_key.is_usable(99, Some("10.0.0.1".parse().unwrap())));
+        assert!(!api_key.is_usable(100, Some("10.0.0.1".parse().unwrap())));
+        assert!(!api_key.is_usable(99, Some("11.0.0.1".parse().unwrap())));
+        assert!(!api_key.is_usable(99, None));
+
+        let user_data = UserData::default();
+        let api_key = user_data.find_key("secret", &user_data.key).unwrap();
+        assert_eq!(api_key.name, "default");
+        assert!(api_key.has_scope(Scope::Delete));
+    }
+
+    #[test]
+    fn test_bucket_names() {
+        let name = BucketNames::default().generate();
+        assert_eq!(name.len(), 16);
+        assert!(name.chars().all(|char| char.is_ascii_alphanumeric()));
+
+        let mut bucket_names: BucketNames =
+            serde_yaml::from_str("style: alphanumeric\nlength: 6\nalphabet: ab1\n").unwrap();
+        bucket_names.load().unwrap();
+        let name = bucket_names.generate();
+        assert_eq!(name.len(), 6);
+        assert!(name.chars().all(|char| "ab1".contains(char)));
+
+        let bucket_names = BucketNames {
+            style: NameStyle::Base32,
+            length: Some(10),
+            ..BucketNames::default()
+        };
+        let name = bucket_names.generate();
+        assert_eq!(name.len(), 10);
+        assert!(name
+            .chars()
+            .all(|char| char.is_ascii_lowercase() || ('2'..='7').contains(&char)));
+
+        let mut bucket_names = BucketNames {
+            style: NameStyle::Words,
+            length: Some(2),
+            words: vec!["otter".into(), "drop-down".into()],
+            ..BucketNames::default()
+        };
+        bucket_names.load().unwrap();
+        assert_eq!(bucket_names.generate(), "OtterOtter");
+
+        let word_list = std::env::temp_dir().join("shares-test-words.txt");
+        std::fs::write(&word_list, "11111\tabacus\n11112\tabdomen\n").unwrap();
+        let mut bucket_names = BucketNames {
+            style: NameStyle::Words,
+            length: Some(1),
+            word_list: Some(word_list.to_string_lossy().to_string()),
+            ..BucketNames::default()
+        };
+        bucket_names.load().unwrap();
+        std::fs::remove_file(&word_list).unwrap();
+        assert_eq!(bucket_names.words, ["abacus", "abdomen"]);
+
+        for invalid in [
+            "style: words\n",
+            "style: words\nwords: [a-b]\n",
+            "alphabet: a-b\n",
+            "length: 0\n",
+            "style: words\nword_list: /does/not/exist\n",
+        ] {
+            let mut bucket_names: BucketNames = serde_yaml::from_str(invalid).unwrap();
+            assert!(bucket_names.load().is_err(), "{}", invalid);
+        }
     }
 }
@@ -0,0 +1,68 @@
+/// A unix timestamp as calendar date and time of day in UTC.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        let (days, seconds) = ((timestamp / 86400) as i64, (timestamp % 86400) as u32);
+
+        // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year,
+            month: month as u32,
+            day: day as u32,
+            hour: seconds / 3600,
+            minute: seconds % 3600 / 60,
+            second: seconds % 60,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::date::DateTime;
+
+    #[test]
+    fn test_from_timestamp() {
+        assert_eq!(
+            DateTime::from_timestamp(1700000000),
+            DateTime {
+                year: 2023,
+                month: 11,
+                day: 14,
+                hour: 22,
+                minute: 13,
+                second: 20,
+            }
+        );
+        assert_eq!(
+            DateTime::from_timestamp(951782400),
+            DateTime {
+                year: 2000,
+                month: 2,
+                day: 29,
+                hour: 0,
+                minute: 0,
+                second: 0,
+            }
+        );
+        assert_eq!(DateTime::from_timestamp(0).year, 1970);
+    }
+}
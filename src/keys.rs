@@ -0,0 +1,260 @@
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hmac, pbkdf2};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+const ALPHABET: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+const PREFIX: &str = "pbkdf2-sha256";
+const ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+/// Enough to find the hash of a key among a few thousand.
+const ID_LEN: usize = 2;
+const MAX_VERIFIED: usize = 1024;
+
+/// Fingerprints of the keys which matched their hash, so a key is only hashed on its first request.
+static VERIFIED: Mutex<Option<HashSet<Vec<u8>>>> = Mutex::new(None);
+
+fn encode(bytes: &[u8]) -> String {
+    base32::encode(ALPHABET, bytes).to_lowercase()
+}
+
+fn decode(value: &str) -> Option<Vec<u8>> {
+    base32::decode(ALPHABET, &value.to_uppercase())
+}
+
+/// Identifies the hash of `key` among all keys without checking each of them.
+///
+/// Keyed with the `key.secret`, which is not stored with the hashes, so without it the ids do not tell
+/// which guesses are worth hashing.
+pub fn key_id(key_secret: &str, key: &str) -> String {
+    let tag = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, key_secret.as_bytes()),
+        key.as_bytes(),
+    );
+    encode(&tag.as_ref()[..ID_LEN])
+}
+
+/// Hashes `key` with a random salt, stored as `pbkdf2-sha256$<id>$<salt>$<hash>`.
+pub fn hash_key(key_secret: &str, key: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    SystemRandom::new()
+        .fill(&mut salt)
+        .expect("Could not generate a salt.");
+    let mut hash = [0u8; HASH_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(ITERATIONS).unwrap(),
+        &salt,
+        key.as_bytes(),
+        &mut hash,
+    );
+
+    format!(
+        "{}${}${}${}",
+        PREFIX,
+        key_id(key_secret, key),
+        encode(&salt),
+        encode(&hash)
+    )
+}
+
+/// Whether a key from the `config.yml` is hashed, otherwise it is a legacy plain text key.
+pub fn is_hashed(stored: &str) -> bool {
+    stored.starts_with(&format!("{}$", PREFIX))
+}
+
+/// Whether `stored` may be the key with the id `id`, which is quick to tell.
+///
+/// Plain text keys are always candidates, they are as quick to check.
+pub fn is_candidate(stored: &str, id: &str) -> bool {
+    if is_hashed(stored) {
+        stored.split('$').nth(1) == Some(id)
+    } else {
+        true
+    }
+}
+
+/// Checks `key` against a hashed or plain text key from the `config.yml` in constant time.
+pub fn verify_key(stored: &str, key: &str) -> bool {
+    if !is_hashed(stored) {
+        // Compare the MACs instead of the keys, so the time taken does not depend on the content.
+        let mac_key = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new())
+            .expect("Could not generate a key.");
+        let tag = hmac::sign(&mac_key, key.as_bytes());
+        return hmac::verify(&mac_key, stored.as_bytes(), tag.as_ref()).is_ok();
+    }
+
+    let (salt, hash) = match stored.split('$').collect::<Vec<_>>().as_slice() {
+        [_, _, salt, hash] => (decode(salt), decode(hash)),
+        _ => return false,
+    };
+    let (salt, hash) = match (salt, hash) {
+        (Some(salt), Some(hash)) if hash.len() == HASH_LEN => (salt, hash),
+        _ => return false,
+    };
+
+    let mut fingerprint = digest::Context::new(&digest::SHA256);
+    fingerprint.update(stored.as_bytes());
+    fingerprint.update(b"$");
+    fingerprint.update(key.as_bytes());
+    let fingerprint = fingerprint.finish().as_ref().to_vec();
+    if let Some(verified) = VERIFIED.lock().unwrap().as_ref() {
+        if verified.contains(&fingerprint) {
+            return true;
+        }
+    }
+
+    let valid = pbkdf2::verify(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(ITERATIONS).unwrap(),
+        &salt,
+        key.as_bytes(),
+        &hash,
+    )
+    .is_ok();
+    if valid {
+        let mut verified = VERIFIED.lock().unwrap();
+        let verified = verified.get_or_insert_with(HashSet::new);
+        if verified.len() >= MAX_VERIFIED {
+            verified.clear();
+        }
+        verified.insert(fingerprint);
+    }
+    valid
+}
+
+/// An address range in CIDR notation, e.g. `10.0.0.0/8` or `::1/128`, a plain address is a single host.
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) if self.network.is_ipv4() => match v6.to_ipv4_mapped() {
+                Some(v4) => IpAddr::V4(v4),
+                None => return false,
+            },
+            ip => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid address range \"{}\"", value);
+        let (network, prefix) = match value.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = network.trim().parse().map_err(|_| invalid())?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+
+        Ok(IpRange { network, prefix })
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> Self {
+        range.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keys::{hash_key, is_candidate, is_hashed, key_id, verify_key, IpRange};
+
+    #[test]
+    fn test_verify_key() {
+        let hash = hash_key("secret", "mysecret");
+        assert!(is_hashed(&hash));
+        assert!(hash.starts_with("pbkdf2-sha256$"));
+        assert_ne!(hash, hash_key("secret", "mysecret"));
+        assert!(verify_key(&hash, "mysecret"));
+        // Verified before, now from the cache.
+        assert!(verify_key(&hash, "mysecret"));
+        assert!(!verify_key(&hash, "mysecret2"));
+        assert!(!verify_key(&hash, ""));
+        assert!(!verify_key("pbkdf2-sha256$!", "mysecret"));
+
+        assert!(!is_hashed("mysecret"));
+        assert!(verify_key("mysecret", "mysecret"));
+        assert!(!verify_key("mysecret", "mysecre"));
+        assert!(!verify_key("mysecret", "mysecret2"));
+    }
+
+    #[test]
+    fn test_is_candidate() {
+        let hash = hash_key("secret", "mysecret");
+        assert!(is_candidate(&hash, &key_id("secret", "mysecret")));
+        assert!(!is_candidate(&hash, &key_id("other secret", "mysecret")));
+        assert!(is_candidate("mysecret", &key_id("secret", "other")));
+        assert_ne!(
+            key_id("secret", "mysecret"),
+            key_id("other secret", "mysecret")
+        );
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+        assert_eq!(range.to_string(), "10.0.0.0/8");
+
+        let host: IpRange = "::1".parse().unwrap();
+        assert!(host.contains("::1".parse().unwrap()));
+        assert!(!host.contains("::2".parse().unwrap()));
+
+        let any: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains("192.168.0.1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+        assert!("localhost".parse::<IpRange>().is_err());
+    }
+}
@@ -0,0 +1,456 @@
+use crate::paste::escape;
+use crate::store::{Bucket, StorageFile, UserDir};
+use crate::thumbnail;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::time::UNIX_EPOCH;
+use urlencoding::encode;
+
+const MAX_PER_PAGE: usize = 1000;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    #[default]
+    Date,
+    Size,
+    Name,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    #[default]
+    Desc,
+}
+
+fn default_page() -> usize {
+    1
+}
+
+fn default_per_page() -> usize {
+    50
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ListQuery {
+    /// Starting at 1.
+    #[serde(default = "default_page")]
+    pub page: usize,
+    #[serde(default = "default_per_page")]
+    pub per_page: usize,
+    #[serde(default)]
+    pub sort: SortBy,
+    #[serde(default)]
+    pub order: Order,
+    /// Glob the filename has to match, `*` matches any characters and `?` a single one.
+    pub name: Option<String>,
+    /// e.g. `image/png`, or `image/*` for all images.
+    pub content_type: Option<String>,
+}
+
+impl Default for ListQuery {
+    fn default() -> Self {
+        ListQuery {
+            page: default_page(),
+            per_page: default_per_page(),
+            sort: SortBy::default(),
+            order: Order::default(),
+            name: None,
+            content_type: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct FileEntry {
+    pub bucket: String,
+    pub filename: String,
+    pub size: u64,
+    /// Unix timestamp of the last modification.
+    pub modified: u64,
+    pub content_type: String,
+    pub expires: Option<u64>,
+    pub url: String,
+    pub thumbnail_url: Option<String>,
+    /// The target of a short link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// How often a short link was followed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clicks: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct FileList {
+    /// The number of files matching the filters, on all pages.
+    pub total: usize,
+    pub page: usize,
+    pub per_page: usize,
+    pub files: Vec<FileEntry>,
+}
+
+/// The files of one bucket, shown on its index page.
+#[derive(Serialize)]
+pub struct BucketIndex {
+    pub bucket: String,
+    /// The size of all files together.
+    pub size: u64,
+    /// All files as one zip archive.
+    pub download_url: String,
+    pub files: Vec<FileEntry>,
+}
+
+/// Whether `name` matches `pattern`, ignoring case.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was seen and how much of the name it consumed, to backtrack to.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|char| *char == '*')
+}
+
+/// Whether `content_type` matches `filter`, either exactly or by its type for `type/*`.
+fn content_type_matches(filter: &str, content_type: &mime::Mime) -> bool {
+    match filter.strip_suffix("/*") {
+        Some(type_) => content_type.type_().as_str().eq_ignore_ascii_case(type_),
+        None => content_type
+            .essence_str()
+            .eq_ignore_ascii_case(filter.trim()),
+    }
+}
+
+/// The entry of a file of `username`, `None` if it is expired or gone.
+async fn entry(
+    username: &str,
+    storage_file: StorageFile<'_, '_, '_, '_>,
+    bucket: &str,
+    public_url: &str,
+) -> Option<FileEntry> {
+    let meta = storage_file.meta().await;
+    if meta.is_expired() {
+        return None;
+    }
+    let metadata = storage_file.metadata().await.ok()?;
+
+    let url = format!(
+        "{}/{}/{}/{}",
+        public_url,
+        encode(username),
+        encode(bucket),
+        encode(&storage_file.name)
+    );
+    let clicks = match meta.redirect {
+        Some(_) => Some(storage_file.clicks().await),
+        None => None,
+    };
+    Some(FileEntry {
+        thumbnail_url: if thumbnail::is_supported(&storage_file.name) {
+            Some(format!("{}/thumb", url))
+        } else {
+            None
+        },
+        url,
+        bucket: bucket.to_string(),
+        size: metadata.size,
+        modified: metadata
+            .modified
+            .duration_since(UNIX_EPOCH)
+            .map(|modified| modified.as_secs())
+            .unwrap_or(0),
+        content_type: storage_file.content_type().to_string(),
+        expires: meta.expires,
+        target: meta.redirect,
+        clicks,
+        filename: storage_file.name,
+    })
+}
+
+/// The files of the user which are not expired, filtered, sorted and paginated as in `query`.
+///
+/// Links name the user by `username`, like the routes serving the files.
+pub async fn files(
+    user_dir: &UserDir<'_, '_>,
+    username: &str,
+    query: &ListQuery,
+    public_url: &str,
+) -> Result<FileList, actix_web::Error> {
+    let mut entries = Vec::new();
+    if user_dir.open(false).await.is_some() {
+        for bucket in user_dir.buckets().await? {
+            let bucket = match Bucket::new(user_dir, Some(bucket)) {
+                Some(bucket) => bucket,
+                None => continue,
+            };
+            for filename in bucket.files().await? {
+                if let Some(pattern) = &query.name {
+                    if !glob_match(pattern, &filename) {
+                        continue;
+                    }
+                }
+                let storage_file = StorageFile::new(&bucket, filename);
+                let content_type = storage_file.content_type();
+                if let Some(filter) = &query.content_type {
+                    if !content_type_matches(filter, &content_type) {
+                        continue;
+                    }
+                }
+                if let Some(entry) = entry(username, storage_file, &bucket.name, public_url).await {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| {
+        let ordering = match query.sort {
+            SortBy::Date => a.modified.cmp(&b.modified),
+            SortBy::Size => a.size.cmp(&b.size),
+            SortBy::Name => a.filename.cmp(&b.filename),
+        };
+        match query.order {
+            Order::Asc => ordering,
+            Order::Desc => ordering.reverse(),
+        }
+    });
+
+    let page = query.page.max(1);
+    let per_page = query.per_page.clamp(1, MAX_PER_PAGE);
+    let total = entries.len();
+    let files = entries
+        .into_iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .collect();
+
+    Ok(FileList {
+        total,
+        page,
+        per_page,
+        files,
+    })
+}
+
+/// The files of `bucket` of `username` which are not expired, sorted by name.
+pub async fn bucket(
+    username: &str,
+    bucket: &Bucket<'_, '_, '_>,
+    public_url: &str,
+) -> Result<BucketIndex, actix_web::Error> {
+    let mut files = Vec::new();
+    if bucket.open(false).await.is_some() {
+        for filename in bucket.files().await? {
+            let storage_file = StorageFile::new(bucket, filename);
+            if let Some(entry) = entry(username, storage_file, &bucket.name, public_url).await {
+                // How often a short link was followed is only for its owner.
+                files.push(FileEntry {
+                    clicks: None,
+                    ..entry
+                });
+            }
+        }
+    }
+    files.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+    Ok(BucketIndex {
+        download_url: format!(
+            "{}/{}/{}.zip",
+            public_url,
+            encode(username),
+            encode(&bucket.name)
+        ),
+        bucket: bucket.name.clone(),
+        size: files.iter().map(|file| file.size).sum(),
+        files,
+    })
+}
+
+/// e.g. `1.5 MiB`.
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", size),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+/// The preview of a file, images as thumbnails and video and audio as players.
+fn preview(file: &FileEntry) -> String {
+    if file.target.is_some() {
+        return String::new();
+    }
+    let url = escape(&file.url);
+    if let Some(thumbnail_url) = &file.thumbnail_url {
+        return format!(
+            r#"<img src="{}" alt="" loading="lazy">"#,
+            escape(thumbnail_url)
+        );
+    }
+    match file.content_type.split('/').next() {
+        Some("image") => format!(r#"<img src="{url}" alt="" loading="lazy">"#),
+        Some("video") => format!(r#"<video src="{url}" controls preload="metadata"></video>"#),
+        Some("audio") => format!(r#"<audio src="{url}" controls preload="none"></audio>"#),
+        _ => String::new(),
+    }
+}
+
+/// Renders the index of a bucket as an HTML page.
+pub fn render(index: &BucketIndex) -> String {
+    let mut files = String::new();
+    for file in &index.files {
+        let details = match &file.target {
+            Some(target) => format!("Link to {}", escape(target)),
+            None => format!(
+                "{} · {}",
+                format_size(file.size),
+                escape(&file.content_type)
+            ),
+        };
+        let _ = write!(
+            files,
+            r#"<li><div class="preview">{}</div><a href="{}">{}</a><span>{}</span></li>"#,
+            preview(file),
+            escape(&file.url),
+            escape(&file.filename),
+            details
+        );
+    }
+    let download_url = escape(&index.download_url);
+    let tar_url = escape(&format!(
+        "{}.tar.gz",
+        index.download_url.trim_end_matches(".zip")
+    ));
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>{bucket} · share.rs</title>
+  <style>
+    body {{ margin: 0; font-family: system-ui, sans-serif; color: #18181b; }}
+    header {{ display: flex; gap: 1rem; align-items: center; padding: .75rem 1rem; background: #18181b; color: #fafafa; }}
+    header h1 {{ margin: 0 auto 0 0; font-size: 1.1rem; }}
+    header a {{ color: #fafafa; }}
+    ul {{ display: grid; grid-template-columns: repeat(auto-fill, minmax(16rem, 1fr)); gap: 1rem; margin: 0; padding: 1rem; list-style: none; }}
+    li {{ display: flex; flex-direction: column; gap: .25rem; overflow-wrap: anywhere; }}
+    li span {{ color: #71717a; font-size: .85rem; }}
+    .preview img, .preview video {{ display: block; max-width: 100%; max-height: 16rem; }}
+    .preview audio {{ width: 100%; }}
+  </style>
+</head>
+<body>
+<header>
+  <h1>{bucket}</h1>
+  <span>{count} files · {size}</span>
+  <a href="{download_url}" download>Download all</a>
+  <a href="{tar_url}" download>.tar.gz</a>
+</header>
+<ul>{files}</ul>
+</body>
+</html>
+"#,
+        bucket = escape(&index.bucket),
+        count = index.files.len(),
+        size = format_size(index.size),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::listing::{
+        content_type_matches, format_size, glob_match, render, BucketIndex, FileEntry,
+    };
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.png", "screenshot.png"));
+        assert!(glob_match("*.PNG", "screenshot.png"));
+        assert!(glob_match("screen*", "screenshot.png"));
+        assert!(glob_match("s?reen*.p*g", "screenshot.png"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*a*b", "aXbab"));
+        assert!(!glob_match("*.png", "screenshot.jpg"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("screenshot", "screenshot.png"));
+    }
+
+    #[test]
+    fn test_content_type_matches() {
+        assert!(content_type_matches("image/*", &mime::IMAGE_PNG));
+        assert!(content_type_matches("image/png", &mime::IMAGE_PNG));
+        assert!(content_type_matches("text/plain", &mime::TEXT_PLAIN_UTF_8));
+        assert!(!content_type_matches("image/jpeg", &mime::IMAGE_PNG));
+        assert!(!content_type_matches("text/*", &mime::IMAGE_PNG));
+    }
+
+    #[test]
+    fn test_render() {
+        let file = |filename: &str, content_type: &str, thumbnail_url: Option<&str>| FileEntry {
+            bucket: "holiday".into(),
+            filename: filename.into(),
+            size: 1536,
+            modified: 0,
+            content_type: content_type.into(),
+            expires: None,
+            url: format!("/user1/holiday/{}", filename),
+            thumbnail_url: thumbnail_url.map(str::to_string),
+            target: None,
+            clicks: None,
+        };
+        let html = render(&BucketIndex {
+            bucket: "holiday".into(),
+            size: 4608,
+            download_url: "/user1/holiday.zip".into(),
+            files: vec![
+                file(
+                    "beach.png",
+                    "image/png",
+                    Some("/user1/holiday/beach.png/thumb"),
+                ),
+                file("clip.mp4", "video/mp4", None),
+                file("<b>.txt", "text/plain", None),
+            ],
+        });
+        assert!(html.contains("<span>3 files · 4.5 KiB</span>"));
+        assert!(html.contains(r#"<a href="/user1/holiday.zip" download>"#));
+        assert!(html.contains(r#"<a href="/user1/holiday.tar.gz" download>"#));
+        assert!(html.contains(r#"<img src="/user1/holiday/beach.png/thumb""#));
+        assert!(html.contains(r#"<video src="/user1/holiday/clip.mp4" controls"#));
+        assert!(html.contains("&lt;b&gt;.txt"));
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+}
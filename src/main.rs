@@ -1,62 +1,728 @@
+mod archive;
+mod backend;
 mod config;
+mod date;
+mod keys;
+mod listing;
+mod paste;
+mod reaper;
+mod sharex;
+mod shortlink;
 mod store;
+mod strip;
+mod thumbnail;
+mod token;
+mod tus;
 
-use std::fs::create_dir;
-
-use crate::config::{Config, UserData};
-use crate::store::{Bucket, StorageFile, UserDir};
-use actix_multipart::Multipart;
-use actix_web::web::Query;
+use crate::backend::{open_backend, StorageBackend};
+use crate::config::{Config, Limits, MatchedKey, ProxyHeader, Scope, UserData};
+use crate::listing::ListQuery;
+use crate::shortlink::LinkTarget;
+use crate::store::{Bucket, FileMeta, StorageFile, UserDir, Written};
+use crate::token::{deletion_token, verify_deletion_token};
+use crate::tus::{TusInfo, TusUpload, TUS_VERSION};
+use actix_multipart::{Field, Multipart};
+use actix_web::error::{
+    ErrorBadRequest, ErrorConflict, ErrorGone, ErrorInternalServerError, ErrorLocked,
+    ErrorNotFound, ErrorPayloadTooLarge, ErrorUnsupportedMediaType, InternalError,
+};
+use actix_web::http::{header, StatusCode, Uri};
+use actix_web::middleware::DefaultHeaders;
+use actix_web::web::{Bytes, Query};
 use actix_web::{
-    delete, get, post, web, App, Error, HttpRequest, HttpResponse, HttpServer, Result,
+    delete, get, head, options, patch, post, put, web, App, Error, HttpRequest, HttpResponse,
+    HttpServer, Result,
 };
-use futures::TryStreamExt;
-use serde::Deserialize;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures::{Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use urlencoding::encode;
 
+/// The web interface for browsing and managing uploads, built on the API below.
+const DASHBOARD: &str = include_str!("pages/dashboard.html");
+/// A page to upload files from the browser, posting to `upload_file`.
+const UPLOAD_PAGE: &str = include_str!("pages/upload.html");
+
 #[derive(Deserialize, Clone)]
-struct AuthQuery {
-    username: String,
-    auth: String,
+struct TokenQuery {
+    token: String,
 }
 
-fn is_authed<'a>(data: &'a Config, username: &str, auth: &str) -> Option<&'a UserData> {
-    let username = String::from(username);
-    match data.users.get(&username) {
-        Some(userdata) => {
-            if auth == userdata.key {
-                Some(userdata)
-            } else {
-                None
+#[derive(Deserialize, Clone)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+struct PasteQuery {
+    language: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PasteForm {
+    text: String,
+    language: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Default)]
+struct ShortenForm {
+    url: Option<String>,
+    expires: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ShortLink {
+    url: String,
+    target: String,
+    deletion_url: String,
+    expires: Option<u64>,
+}
+
+/// The filename a short link is stored as in its own bucket.
+const LINK_FILE: &str = "link";
+const MAX_LINK_BODY_SIZE: usize = 8 * 1024;
+/// Pastes sent as a form are read into memory before they are stored.
+const MAX_PASTE_FORM_SIZE: usize = 16 * 1024 * 1024;
+/// Larger files are not shown in the viewer but redirected to their raw content.
+const MAX_VIEW_SIZE: u64 = 8 * 1024 * 1024;
+/// Images are read into memory to remove their metadata, larger ones are stored as they are.
+const MAX_STRIP_SIZE: u64 = 64 * 1024 * 1024;
+/// Thumbnails are generated in memory, larger images are refused.
+const MAX_THUMBNAIL_SOURCE_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Serialize)]
+struct Upload {
+    url: String,
+    deletion_url: String,
+    thumbnail_url: Option<String>,
+    /// The page showing text files with syntax highlighting.
+    view_url: Option<String>,
+    /// Whether metadata like EXIF was removed from the image.
+    metadata_stripped: bool,
+    size: u64,
+    content_type: String,
+    /// Hex encoded SHA-256 of the content.
+    checksum: String,
+    expires: Option<u64>,
+    #[serde(skip)]
+    path: String,
+    #[serde(skip)]
+    deletion_path: String,
+}
+
+/// Whether the client asked for JSON with an `Accept` header or `?format=json`.
+fn wants_json(request: &HttpRequest, query: &FormatQuery) -> bool {
+    query.format.as_deref() == Some("json")
+        || request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+            .map(|accept| accept.contains("application/json"))
+            .unwrap_or(false)
+}
+
+/// Credentials sent with a request, without a `username` the key alone identifies the user.
+struct Credentials {
+    username: Option<String>,
+    key: String,
+}
+
+/// A user authenticated by the credentials of a request.
+struct Authed<'a> {
+    username: &'a str,
+    user_data: &'a UserData,
+    api_key: MatchedKey<'a>,
+    /// The key as sent by the client.
+    key: String,
+}
+
+fn auth_error(status: StatusCode, error: Option<&str>) -> Error {
+    let bearer = match error {
+        Some(error) => format!("Bearer realm=\"share.rs\", error=\"{}\"", error),
+        None => "Bearer realm=\"share.rs\"".to_string(),
+    };
+    let response = HttpResponse::build(status)
+        .append_header((header::WWW_AUTHENTICATE, bearer))
+        .append_header((header::WWW_AUTHENTICATE, "Basic realm=\"share.rs\""))
+        .finish();
+
+    InternalError::from_response("Invalid credentials", response).into()
+}
+
+fn invalid_credentials() -> Error {
+    auth_error(StatusCode::BAD_REQUEST, Some("invalid_request"))
+}
+
+/// Reads the credentials from the `Authorization` header, or the `username` and `auth` headers.
+fn credentials(request: &HttpRequest) -> Result<Option<Credentials>, Error> {
+    let headers = request.headers();
+    if let Some(authorization) = headers.get(header::AUTHORIZATION) {
+        let authorization = authorization.to_str().map_err(|_| invalid_credentials())?;
+        let (scheme, value) = authorization
+            .trim()
+            .split_once(' ')
+            .ok_or_else(invalid_credentials)?;
+        let value = value.trim();
+        if value.is_empty() {
+            return Err(invalid_credentials());
+        }
+
+        return if scheme.eq_ignore_ascii_case("bearer") {
+            Ok(Some(Credentials {
+                username: None,
+                key: value.to_string(),
+            }))
+        } else if scheme.eq_ignore_ascii_case("basic") {
+            let decoded = BASE64
+                .decode(value)
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .ok_or_else(invalid_credentials)?;
+            let (username, key) = decoded.split_once(':').ok_or_else(invalid_credentials)?;
+
+            Ok(Some(Credentials {
+                username: Some(username.to_string()).filter(|username| !username.is_empty()),
+                key: key.to_string(),
+            }))
+        } else {
+            Err(auth_error(StatusCode::UNAUTHORIZED, None))
+        };
+    }
+
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .map(|value| value.to_str().map(str::to_string))
+            .transpose()
+            .map_err(|_| invalid_credentials())
+    };
+    match (header("username")?, header("auth")?) {
+        (Some(username), Some(key)) => Ok(Some(Credentials {
+            username: Some(username),
+            key,
+        })),
+        _ => Ok(None),
+    }
+}
+
+fn is_authed<'a>(
+    data: &'a Config,
+    username: &str,
+    auth: &str,
+) -> Option<(&'a UserData, MatchedKey<'a>)> {
+    let user_data = data.users.get(username)?;
+
+    user_data
+        .find_key(&data.key_secret, auth)
+        .map(|api_key| (user_data, api_key))
+}
+
+/// The user owning the key `auth`, only verifying the key with the same id instead of every key.
+fn find_user_by_key<'a>(
+    data: &'a Config,
+    auth: &str,
+) -> Option<(&'a String, &'a UserData, MatchedKey<'a>)> {
+    let mut users: Vec<(&String, &UserData)> = data.users.iter().collect();
+    users.sort_by_key(|(username, _)| *username);
+    let id = keys::key_id(&data.key_secret, auth);
+    users.iter().find_map(|(username, user_data)| {
+        user_data
+            .find_candidate_key(auth, &id)
+            .map(|api_key| (*username, *user_data, api_key))
+    })
+}
+
+/// An address from `X-Forwarded-For` or the `for` of `Forwarded`, possibly quoted, in brackets or with a port.
+fn parse_forwarded_addr(addr: &str) -> Option<IpAddr> {
+    let addr = addr.trim().trim_matches('"');
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            addr.strip_prefix('[')
+                .and_then(|addr| addr.strip_suffix(']'))
+                .and_then(|addr| addr.parse().ok())
+        })
+}
+
+/// The addresses the request was forwarded for in the `proxy_header`, the proxy in front of the server appended the
+/// last one.
+fn forwarded_addrs(request: &HttpRequest, proxy_header: ProxyHeader) -> Vec<String> {
+    let values = |name: &str| -> Vec<String> {
+        request
+            .headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|addr| addr.trim().to_string())
+            .collect()
+    };
+
+    match proxy_header {
+        ProxyHeader::XForwardedFor => values("x-forwarded-for"),
+        ProxyHeader::Forwarded => values(header::FORWARDED.as_str())
+            .iter()
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (name, value) = pair.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| value.trim().to_string())
+                })
+            })
+            .collect(),
+    }
+}
+
+/// The address of the client, taken from the `proxy_header` if `behind_proxy` is set.
+///
+/// Clients can send the header themselves, so only the addresses appended by the proxy in front of the server and
+/// the `trusted_proxies` before it count, the first one from the right which is not a trusted proxy is the client.
+/// If one of them is no address, like `unknown`, the peer address is used instead.
+fn client_ip(config: &Config, request: &HttpRequest) -> Option<IpAddr> {
+    let peer = request.peer_addr().map(|addr| addr.ip());
+    if !config.behind_proxy {
+        return peer;
+    }
+
+    let addrs = forwarded_addrs(request, config.proxy_header);
+    for addr in addrs.iter().rev() {
+        let ip = match parse_forwarded_addr(addr) {
+            Some(ip) => ip,
+            None => return peer,
+        };
+        if !config
+            .trusted_proxies
+            .iter()
+            .any(|range| range.contains(ip))
+        {
+            return Some(ip);
+        }
+    }
+    addrs
+        .first()
+        .and_then(|addr| parse_forwarded_addr(addr))
+        .or(peer)
+}
+
+/// The user authenticated by the request, malformed credentials are rejected with an error.
+///
+/// Keys which are expired or restricted to other addresses do not authenticate.
+fn is_authed_header<'a>(
+    data: &'a Config,
+    request: &HttpRequest,
+) -> Result<Option<Authed<'a>>, Error> {
+    let credentials = match credentials(request)? {
+        Some(credentials) => credentials,
+        None => return Ok(None),
+    };
+    let user = match &credentials.username {
+        Some(username) => data
+            .users
+            .get_key_value(username)
+            .and_then(|(username, _)| {
+                is_authed(data, username, &credentials.key)
+                    .map(|(user_data, api_key)| (username, user_data, api_key))
+            }),
+        None => find_user_by_key(data, &credentials.key),
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let ip = client_ip(data, request);
+
+    Ok(user
+        .filter(|(_, _, api_key)| api_key.is_usable(now, ip))
+        .map(|(username, user_data, api_key)| Authed {
+            username,
+            user_data,
+            api_key,
+            key: credentials.key,
+        }))
+}
+
+/// Parses an `expires` value, seconds with an optional `m`, `h`, `d` or `w` suffix or `never`.
+fn parse_expires(value: &str) -> Result<Option<u64>, Error> {
+    let value = value.trim();
+    if value == "never" {
+        return Ok(None);
+    }
+
+    let (number, unit) = match value.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => (&value[..index], unit),
+        _ => (value, 's'),
+    };
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(ErrorBadRequest("Invalid expires")),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .map(Some)
+        .ok_or_else(|| ErrorBadRequest("Invalid expires"))
+}
+
+/// The expiry in seconds from the `expires` header, or the default of the user.
+fn requested_expiry(request: &HttpRequest, user_data: &UserData) -> Result<Option<u64>, Error> {
+    match request.headers().get("expires") {
+        Some(value) => parse_expires(
+            value
+                .to_str()
+                .map_err(|_| ErrorBadRequest("Invalid expires"))?,
+        ),
+        None => Ok(user_data.expire_after),
+    }
+}
+
+/// The unix timestamp `expires` seconds from now.
+fn expires_at(expires: Option<u64>) -> Option<u64> {
+    expires.map(|expires| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .saturating_add(expires)
+    })
+}
+
+/// The name of a new bucket chosen with the `bucket` header.
+fn requested_bucket(request: &HttpRequest) -> Result<Option<String>, Error> {
+    request
+        .headers()
+        .get("bucket")
+        .map(|bucket| {
+            bucket
+                .to_str()
+                .map(|bucket| bucket.trim().to_string())
+                .map_err(|_| ErrorBadRequest("Invalid bucket name"))
+        })
+        .transpose()
+}
+
+/// A new bucket named `name`, or with a random name if none was chosen.
+async fn new_bucket<'a, 'b, 'c>(
+    user_dir: &'a UserDir<'b, 'c>,
+    name: Option<String>,
+) -> Result<Bucket<'a, 'b, 'c>, Error> {
+    match name {
+        Some(name) => Bucket::claim(user_dir, name).await,
+        None => Bucket::random(user_dir).await,
+    }
+}
+
+/// The bucket `name` of the user, claimed like a requested bucket if it does not exist yet.
+///
+/// Unlike the `bucket` header this adds to an existing bucket, so files can be put into it one by one.
+async fn own_bucket<'a, 'b, 'c>(
+    user_dir: &'a UserDir<'b, 'c>,
+    name: String,
+) -> Result<Bucket<'a, 'b, 'c>, Error> {
+    match Bucket::claim(user_dir, name.clone()).await {
+        Err(err) if err.as_response_error().status_code() == StatusCode::CONFLICT => {
+            Bucket::new(user_dir, Some(name)).ok_or_else(|| ErrorBadRequest("Invalid bucket name"))
+        }
+        result => result,
+    }
+}
+
+/// Describes a file written by an upload, with the links to it.
+fn uploaded(
+    config: &Config,
+    username: &str,
+    bucket: &Bucket,
+    storage_file: &StorageFile,
+    written: Written,
+    expires: Option<u64>,
+) -> Upload {
+    // The links name the user like the routes looking them up, not by their folder.
+    let path = format!(
+        "{}/{}/{}",
+        encode(username),
+        encode(&bucket.name),
+        encode(&storage_file.name)
+    );
+    let deletion_path = format!(
+        "delete/{}/{}/{}?token={}",
+        encode(username),
+        encode(&bucket.name),
+        encode(&storage_file.name),
+        deletion_token(
+            &config.secret,
+            &format!("{}/{}/{}", username, &bucket.name, &storage_file.name),
+            written.created,
+            &written.sha256
+        )
+    );
+    let content_type = storage_file.content_type();
+    Upload {
+        url: format!("{}/{}", config.public_url(), path),
+        deletion_url: format!("{}/{}", config.public_url(), deletion_path),
+        thumbnail_url: if thumbnail::is_supported(&storage_file.name) {
+            Some(format!("{}/{}/thumb", config.public_url(), path))
+        } else {
+            None
+        },
+        view_url: if paste::is_viewable(&storage_file.name, &content_type) {
+            Some(format!("{}/{}/view", config.public_url(), path))
+        } else {
+            None
+        },
+        metadata_stripped: false,
+        size: written.size,
+        content_type: content_type.to_string(),
+        checksum: written.sha256,
+        expires,
+        path,
+        deletion_path,
+    }
+}
+
+/// Writes an uploaded file, removing the image metadata first if `user_data` wants that.
+/// Also returns whether metadata was removed.
+async fn write_upload<S, E>(
+    config: &Config,
+    user_data: &UserData,
+    storage_file: &StorageFile<'_, '_, '_, '_>,
+    mut stream: S,
+    meta: FileMeta,
+) -> Result<(Written, bool), Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    if config.strip_metadata(user_data) && strip::is_supported(&storage_file.name) {
+        let limit = config
+            .limits(user_data)
+            .max_file_size
+            .map_or(MAX_STRIP_SIZE, |max| max.min(MAX_STRIP_SIZE));
+        let mut content = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(Into::into)?;
+            if (content.len() + chunk.len()) as u64 > limit {
+                // Too large to strip in memory, the write still fails if it exceeds the limits of the user.
+                let read = futures::stream::iter([Ok(Bytes::from(content)), Ok(chunk)]);
+                let rest = stream.map(|chunk| chunk.map_err(Into::into));
+                return Ok((storage_file.write(read.chain(rest), meta).await?, false));
             }
+            content.extend_from_slice(&chunk);
         }
-        None => None,
+        let stripped = strip::strip(&content);
+        let metadata_stripped = stripped.is_some();
+        let written = storage_file
+            .write(
+                futures::stream::iter([Ok::<_, Error>(Bytes::from(stripped.unwrap_or(content)))]),
+                meta,
+            )
+            .await?;
+        Ok((written, metadata_stripped))
+    } else {
+        Ok((storage_file.write(stream, meta).await?, false))
     }
 }
 
-fn is_authed_header<'a>(data: &'a Config, request: &HttpRequest) -> Option<&'a UserData> {
-    let headers = request.headers();
-    let username = headers.get("username").map(|user| user.to_str().unwrap());
-    let auth = headers.get("auth").map(|user| user.to_str().unwrap());
+/// Adds `Tus-Resumable` to every response of the tus routes, errors included.
+fn tus_headers() -> DefaultHeaders {
+    DefaultHeaders::new().add(("Tus-Resumable", TUS_VERSION))
+}
 
-    if let (Some(username), Some(auth)) = (username, auth) {
-        is_authed(data, username, auth)
+/// Fails with 412 unless the request speaks the tus version of the server.
+fn tus_resumable(request: &HttpRequest) -> Result<(), Error> {
+    if request
+        .headers()
+        .get("Tus-Resumable")
+        .and_then(|version| version.to_str().ok())
+        == Some(TUS_VERSION)
+    {
+        Ok(())
     } else {
-        None
+        Err(InternalError::from_response(
+            "Unsupported tus version",
+            HttpResponse::PreconditionFailed()
+                .insert_header(("Tus-Version", TUS_VERSION))
+                .finish(),
+        )
+        .into())
+    }
+}
+
+/// Parses a numeric tus header like `Upload-Length`, failing with 400 if it is missing.
+fn tus_number(request: &HttpRequest, name: &str) -> Result<u64, Error> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| ErrorBadRequest(format!("Missing or invalid {}", name)))
+}
+
+/// The resumable upload `id` of `username`, fails with 404 for uploads of other users and 410 once it expired.
+async fn tus_upload<'a>(
+    backend: &'a dyn StorageBackend,
+    id: String,
+    username: &str,
+) -> Result<(TusUpload<'a>, TusInfo), Error> {
+    let upload =
+        TusUpload::new(backend, Some(id)).ok_or_else(|| ErrorNotFound("Upload Not Found"))?;
+    let info = upload
+        .info()
+        .await
+        .filter(|info| info.username == username)
+        .ok_or_else(|| ErrorNotFound("Upload Not Found"))?;
+    if info.is_expired() {
+        return Err(ErrorGone("Upload Expired"));
+    }
+
+    Ok((upload, info))
+}
+
+fn http_date(timestamp: u64) -> header::HttpDate {
+    header::HttpDate::from(UNIX_EPOCH + Duration::from_secs(timestamp))
+}
+
+/// Whether the request body is an url encoded form.
+fn is_form(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .and_then(|content_type| content_type.parse::<mime::Mime>().ok())
+        .map(|content_type| {
+            content_type.essence_str() == mime::APPLICATION_WWW_FORM_URLENCODED.essence_str()
+        })
+        .unwrap_or(false)
+}
+
+/// Reads the whole request body or field, failing with 413 if it is longer than `limit`.
+async fn read_body<S, E>(stream: &mut S, limit: usize) -> Result<Vec<u8>, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Error>,
+{
+    let mut body = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(Into::into)?;
+        if body.len() + chunk.len() > limit {
+            return Err(ErrorPayloadTooLarge("Request body too large"));
+        }
+        body.extend_from_slice(&chunk);
     }
+
+    Ok(body)
 }
 
-fn is_authed_query<'a>(data: &'a Config, auth_query: &AuthQuery) -> Option<&'a UserData> {
-    let username = auth_query.username.as_str();
-    let auth = auth_query.auth.as_str();
+async fn read_text_field(field: &mut Field) -> Result<String, Error> {
+    let mut content = Vec::new();
+    while let Some(chunk) = field.try_next().await? {
+        content.extend_from_slice(&chunk);
+        if content.len() > 64 {
+            return Err(ErrorBadRequest("Form field too long"));
+        }
+    }
+
+    String::from_utf8(content).map_err(|_| ErrorBadRequest("Form field is not utf-8"))
+}
 
-    is_authed(data, username, auth)
+/// Registers all routes, the first matching one handles a request.
+///
+/// Fixed prefixes like `/delete/...` come before `/{user}/{bucket}/{filename}/thumb` and `/view`,
+/// which would otherwise match the deletion link of a file named `thumb` or `view`.
+fn services(config: &mut web::ServiceConfig) {
+    config
+        .service(upload_file)
+        .service(put_file)
+        .service(put_file_in_bucket)
+        .service(paste_text)
+        .service(shorten_url)
+        .service(tus_options)
+        .service(tus_create)
+        .service(tus_offset)
+        .service(tus_append)
+        .service(tus_terminate)
+        .service(follow_short_link)
+        .service(sharex_uploader)
+        .service(get_delete_file)
+        .service(view_file)
+        .service(find_thumbnail)
+        .service(delete_file)
+        .service(list_files)
+        .service(dashboard)
+        .service(upload_page)
+        .service(download_bucket)
+        .service(bucket_index)
+        .service(find_file);
 }
 
 #[actix_web::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    // Only needs the key secret, so it never creates a config.yml with a plain text key.
+    match args.as_slice() {
+        ["hash-key"] => {
+            let key_secret = Config::load_key_secret(false)?;
+            let key = config::random_key();
+            println!("key: {}", key);
+            println!("hash: {}", keys::hash_key(&key_secret, &key));
+            return Ok(());
+        }
+        ["hash-key", key] => {
+            println!("{}", keys::hash_key(&Config::load_key_secret(false)?, key));
+            return Ok(());
+        }
+        _ => {}
+    }
+
     let config = Config::load()?;
+    match args.as_slice() {
+        [] => {}
+        ["sharex", username, rest @ ..] if rest.len() <= 1 => {
+            let user_data = config
+                .users
+                .get(*username)
+                .ok_or_else(|| format!("Unknown user \"{}\"", username))?;
+            let key = match rest.first() {
+                Some(key) if user_data.find_key(&config.key_secret, key).is_some() => {
+                    key.to_string()
+                }
+                Some(_) => return Err(format!("Wrong key for user \"{}\"", username).into()),
+                None if !user_data.key.is_empty() && !keys::is_hashed(&user_data.key) => {
+                    user_data.key.clone()
+                }
+                None => {
+                    return Err(format!(
+                        "The key of user \"{}\" is hashed, pass it with `shares sharex {} <key>`",
+                        username, username
+                    )
+                    .into())
+                }
+            };
+            let uploader = sharex::custom_uploader(&config, username, &key);
+            println!("{}", serde_json::to_string_pretty(&uploader)?);
+            return Ok(());
+        }
+        _ => {
+            eprintln!("Usage:");
+            eprintln!("  shares                            Start the server");
+            eprintln!("  shares hash-key [key]             Hash a key (or a new random one) for the config.yml");
+            eprintln!(
+                "  shares sharex <username> [key]    Print a ShareX custom uploader for the user"
+            );
+            std::process::exit(2);
+        }
+    }
 
     let addr = format!("{}:{}", config.host, config.port);
     println!("Starting Server at {}", addr);
@@ -67,16 +733,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             username, userdata.folder
         )
     });
-    let storage_folder = Path::new(config.storage_folder.as_str());
-    create_dir(storage_folder).unwrap_or(());
+    let backend = open_backend(&config);
+    actix_web::rt::spawn(reaper::run(config.clone(), backend.clone()));
 
     HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(config.clone()))
-            .service(upload_file)
-            .service(get_delete_file)
-            .service(delete_file)
-            .service(find_file)
+            .app_data(web::Data::from(backend.clone()))
+            .configure(services)
     })
     .bind(addr)?
     .run()
@@ -85,16 +749,292 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[get("/sharex.sxcu")]
+async fn sharex_uploader(
+    config: web::Data<Config>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(authed) = authed {
+        Ok(HttpResponse::Ok()
+            .insert_header(header::ContentDisposition::attachment(sharex::file_name(
+                &config,
+                authed.username,
+            )))
+            .json(sharex::custom_uploader(
+                &config,
+                authed.username,
+                &authed.key,
+            )))
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+#[get("/api/files")]
+async fn list_files(
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    query: Query<ListQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::List));
+    if let Some(Authed {
+        username,
+        user_data,
+        ..
+    }) = authed
+    {
+        let user_dir = UserDir::new(backend.get_ref(), user_data);
+        let files = listing::files(&user_dir, username, &query, &config.public_url()).await?;
+
+        Ok(HttpResponse::Ok().json(files))
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+#[get("/dashboard")]
+async fn dashboard() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(mime::TEXT_HTML_UTF_8)
+        .body(DASHBOARD)
+}
+
+#[get("/upload")]
+async fn upload_page() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(mime::TEXT_HTML_UTF_8)
+        .body(UPLOAD_PAGE)
+}
+
+#[get("/{user}/{bucket}/{filename}/thumb")]
+async fn find_thumbnail(
+    path: web::Path<(String, String, String)>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+) -> Result<HttpResponse, Error> {
+    let (user, bucket, filename) = path.into_inner();
+    let userdata = config
+        .users
+        .get(&user)
+        .ok_or_else(|| ErrorNotFound("File Not Found"))?;
+    let user_dir = UserDir::new(backend.get_ref(), userdata);
+    let bucket =
+        Bucket::new(&user_dir, Some(bucket)).ok_or_else(|| ErrorNotFound("File Not Found"))?;
+    let storage_file = StorageFile::new(&bucket, filename);
+    if !thumbnail::is_supported(&storage_file.name)
+        || !storage_file.exists().await
+        || storage_file.is_expired().await
+    {
+        return Err(ErrorNotFound("File Not Found"));
+    }
+
+    let thumbnail = match storage_file.thumbnail().await {
+        Some(thumbnail) => thumbnail,
+        None => {
+            if storage_file.metadata().await?.size > MAX_THUMBNAIL_SOURCE_SIZE {
+                return Err(ErrorPayloadTooLarge("Image too large for a thumbnail"));
+            }
+            let object = storage_file
+                .open(None)
+                .await
+                .ok_or_else(|| ErrorNotFound("File Not Found"))?;
+            let content: Vec<Bytes> = object.body.try_collect().await?;
+            let (width, height) = (config.thumbnail_width, config.thumbnail_height);
+            let thumbnail =
+                web::block(move || thumbnail::generate(&content.concat(), width, height))
+                    .await?
+                    .map_err(|_| ErrorUnsupportedMediaType("Not a supported image"))?;
+
+            println!(
+                "Generated Thumbnail for: {}/{}/{}",
+                &userdata.folder, &bucket.name, &storage_file.name
+            );
+            if let Err(error) = storage_file.write_thumbnail(&thumbnail).await {
+                eprintln!(
+                    "Could not cache the thumbnail of {}: {}",
+                    storage_file.key(),
+                    error
+                );
+            }
+            thumbnail
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(thumbnail::content_type(&thumbnail))
+        .body(thumbnail))
+}
+
+#[get("/{user}/{bucket}/{filename}/view")]
+async fn view_file(
+    path: web::Path<(String, String, String)>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    query: Query<PasteQuery>,
+) -> Result<HttpResponse, Error> {
+    let (user, bucket, filename) = path.into_inner();
+    let userdata = config
+        .users
+        .get(&user)
+        .ok_or_else(|| ErrorNotFound("File Not Found"))?;
+    let user_dir = UserDir::new(backend.get_ref(), userdata);
+    let bucket =
+        Bucket::new(&user_dir, Some(bucket)).ok_or_else(|| ErrorNotFound("File Not Found"))?;
+    let storage_file = StorageFile::new(&bucket, filename);
+    let meta = storage_file.meta().await;
+    if !storage_file.exists().await || meta.is_expired() {
+        return Err(ErrorNotFound("File Not Found"));
+    }
+
+    let raw_url = format!(
+        "{}/{}/{}/{}",
+        config.public_url(),
+        encode(&user),
+        encode(&bucket.name),
+        encode(&storage_file.name)
+    );
+    if storage_file.metadata().await?.size > MAX_VIEW_SIZE {
+        return Ok(HttpResponse::Found()
+            .insert_header((header::LOCATION, raw_url))
+            .finish());
+    }
+    let object = storage_file
+        .open(None)
+        .await
+        .ok_or_else(|| ErrorNotFound("File Not Found"))?;
+    let content: Vec<Bytes> = object.body.try_collect().await?;
+    let text = String::from_utf8(content.concat())
+        .map_err(|_| ErrorUnsupportedMediaType("Not a text file"))?;
+
+    // Highlighting takes a while for long texts, so it does not hold up the other requests of the worker.
+    let filename = storage_file.name.clone();
+    let language = query.into_inner().language.or(meta.language);
+    let html =
+        web::block(move || paste::render(&filename, &raw_url, &text, language.as_deref())).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(mime::TEXT_HTML_UTF_8)
+        .body(html))
+}
+
+/// Streams all files of a bucket as one archive, e.g. `/user1/holiday.zip`.
+#[get("/{user}/{bucket:[^/.]+}.{format:zip|tar\\.gz}")]
+async fn download_bucket(
+    path: web::Path<(String, String, String)>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+) -> Result<HttpResponse, Error> {
+    let (user, bucket, format) = path.into_inner();
+    let format = archive::Format::from_extension(&format)
+        .ok_or_else(|| ErrorNotFound("Bucket Not Found"))?;
+    let userdata = config
+        .users
+        .get(&user)
+        .filter(|userdata| config.public_listing(userdata))
+        .ok_or_else(|| ErrorNotFound("Bucket Not Found"))?;
+    let user_dir = UserDir::new(backend.get_ref(), userdata);
+    let bucket =
+        Bucket::new(&user_dir, Some(bucket)).ok_or_else(|| ErrorNotFound("Bucket Not Found"))?;
+
+    let mut entries = Vec::new();
+    if bucket.open(false).await.is_some() {
+        for filename in bucket.files().await? {
+            let storage_file = StorageFile::new(&bucket, filename);
+            if storage_file.is_expired().await {
+                continue;
+            }
+            let metadata = storage_file.metadata().await?;
+            entries.push(archive::Entry {
+                name: format!("{}/{}", bucket.name, storage_file.name),
+                key: storage_file.content_key().await,
+                size: metadata.size,
+                modified: metadata
+                    .modified
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |modified| modified.as_secs()),
+            });
+        }
+    }
+    if entries.is_empty() {
+        return Err(ErrorNotFound("Bucket Not Found"));
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    println!(
+        "Archiving Bucket: {}/{}.{}",
+        &userdata.folder,
+        &bucket.name,
+        format.extension()
+    );
+    let mut response = HttpResponse::Ok();
+    response
+        .content_type(format.content_type())
+        .insert_header(header::ContentDisposition {
+            disposition: header::DispositionType::Attachment,
+            parameters: vec![header::DispositionParam::Filename(format!(
+                "{}.{}",
+                bucket.name,
+                format.extension()
+            ))],
+        });
+    if let Some(length) = archive::length(format, &entries) {
+        response.no_chunking(length);
+    }
+
+    Ok(response.streaming(archive::stream(backend.into_inner(), format, entries)))
+}
+
+/// Lists the files of a bucket as a page, or as JSON with `Accept: application/json` or `?format=json`.
+#[get("/{user}/{bucket}")]
+async fn bucket_index(
+    path: web::Path<(String, String)>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    query: Query<FormatQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (user, bucket) = path.into_inner();
+    let userdata = config
+        .users
+        .get(&user)
+        .filter(|userdata| config.public_listing(userdata))
+        .ok_or_else(|| ErrorNotFound("Bucket Not Found"))?;
+    let user_dir = UserDir::new(backend.get_ref(), userdata);
+    let bucket =
+        Bucket::new(&user_dir, Some(bucket)).ok_or_else(|| ErrorNotFound("Bucket Not Found"))?;
+    let index = listing::bucket(&user, &bucket, &config.public_url()).await?;
+    if index.files.is_empty() {
+        return Err(ErrorNotFound("Bucket Not Found"));
+    }
+
+    println!("Listing Bucket: {}/{}", &userdata.folder, &bucket.name);
+    if wants_json(&request, &query) {
+        Ok(HttpResponse::Ok().json(index))
+    } else {
+        Ok(HttpResponse::Ok()
+            .content_type(mime::TEXT_HTML_UTF_8)
+            .body(listing::render(&index)))
+    }
+}
+
 #[get("/{user}/{bucket}/{filename}")]
 async fn find_file(
     path: web::Path<(String, String, String)>,
     config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
     req: HttpRequest,
 ) -> HttpResponse {
     let (user, bucket, filename) = path.into_inner();
-    if let Some(userdata) = config.users.get(&*user) {
-        let user_dir = UserDir::new(&config, userdata);
-        let bucket = Bucket::new(&user_dir, Some(bucket)).unwrap();
+    if let Some(userdata) = config.users.get(&user) {
+        let user_dir = UserDir::new(backend.get_ref(), userdata);
+        let bucket = match Bucket::new(&user_dir, Some(bucket)) {
+            Some(bucket) => bucket,
+            None => return HttpResponse::NotFound().finish(),
+        };
         let storage_file = StorageFile::new(&bucket, filename);
 
         println!(
@@ -107,16 +1047,49 @@ async fn find_file(
     }
 }
 
+/// Follows a short link by the id `/shorten` returned.
+#[get("/s/{id}")]
+async fn follow_short_link(
+    path: web::Path<String>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    req: HttpRequest,
+) -> HttpResponse {
+    let id = path.into_inner();
+    if let Some(target) = shortlink::resolve(backend.get_ref(), &id).await {
+        if let Some(userdata) = config.users.get(&target.username) {
+            let user_dir = UserDir::new(backend.get_ref(), userdata);
+            if let Some(bucket) = Bucket::new(&user_dir, Some(target.bucket)) {
+                let storage_file = StorageFile::new(&bucket, LINK_FILE.to_string());
+                // The bucket may hold a later link since, which has an id of its own.
+                if storage_file.meta().await.short_link.as_deref() == Some(id.as_str()) {
+                    return storage_file.serve(&req).await;
+                }
+            }
+        }
+    }
+
+    HttpResponse::NotFound().finish()
+}
+
 #[delete("/{bucket}/{filename}")]
 async fn delete_file(
     path: web::Path<(String, String)>,
     config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
     request: HttpRequest,
 ) -> Result<HttpResponse, Error> {
-    let (bucket, filename) = path.into_inner();
-    if let Some(userdata) = is_authed_header(config.get_ref(), &request) {
-        let user_dir = UserDir::new(&config, userdata);
-        let bucket = Bucket::new(&user_dir, Some(bucket)).unwrap();
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Delete));
+    if let Some(Authed {
+        user_data: userdata,
+        ..
+    }) = authed
+    {
+        let (bucket, filename) = path.into_inner();
+        let user_dir = UserDir::new(backend.get_ref(), userdata);
+        let bucket =
+            Bucket::new(&user_dir, Some(bucket)).ok_or_else(|| ErrorNotFound("File Not Found"))?;
         let storage_file = StorageFile::new(&bucket, filename);
 
         println!(
@@ -131,18 +1104,35 @@ async fn delete_file(
     }
 }
 
-#[get("delete/{bucket}/{filename}")]
+#[get("delete/{user}/{bucket}/{filename}")]
 async fn get_delete_file(
-    path: web::Path<(String, String)>,
+    path: web::Path<(String, String, String)>,
     config: web::Data<Config>,
-    query: Query<AuthQuery>,
+    backend: web::Data<dyn StorageBackend>,
+    query: Query<TokenQuery>,
 ) -> Result<HttpResponse, Error> {
-    let (bucket, filename) = path.into_inner();
-    if let Some(userdata) = is_authed_query(config.get_ref(), &query) {
-        let user_dir = UserDir::new(&config, userdata);
-        let bucket = Bucket::new(&user_dir, Some(bucket)).unwrap();
+    let (user, bucket, filename) = path.into_inner();
+    if let Some(userdata) = config.users.get(&user) {
+        let user_dir = UserDir::new(backend.get_ref(), userdata);
+        let bucket =
+            Bucket::new(&user_dir, Some(bucket)).ok_or_else(|| ErrorNotFound("File Not Found"))?;
         let storage_file = StorageFile::new(&bucket, filename);
 
+        if !storage_file.exists().await {
+            return Err(ErrorNotFound("File Not Found"));
+        }
+        let path = format!("{}/{}/{}", user, &bucket.name, &storage_file.name);
+        let meta = storage_file.meta().await;
+        let verified = match (meta.created, &meta.sha256) {
+            (Some(created), Some(sha256)) => {
+                verify_deletion_token(&config.secret, &path, created, sha256, &query.token)
+            }
+            _ => false,
+        };
+        if !verified {
+            return Ok(HttpResponse::Forbidden().finish());
+        }
+
         println!(
             "Deleting File from: {}/{}/{}",
             &userdata.folder, &bucket.name, &storage_file.name
@@ -159,362 +1149,3222 @@ async fn get_delete_file(
 async fn upload_file(
     mut payload: Multipart,
     config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    format: Query<FormatQuery>,
     request: HttpRequest,
 ) -> Result<HttpResponse, Error> {
-    if let Some(user_data) = is_authed_header(config.get_ref(), &request) {
-        let mut files: Vec<String> = Vec::new();
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(Authed {
+        username,
+        user_data,
+        ..
+    }) = authed
+    {
+        let mut expires = requested_expiry(&request, user_data)?;
+        let mut bucket_name = requested_bucket(&request)?;
+        // Once set, the remaining files go into this bucket too.
+        let mut group: Option<String> = None;
+        let user_dir = UserDir::new(backend.get_ref(), user_data)
+            .with_limits(config.limits(user_data))
+            .with_dedup(config.dedup)
+            .with_bucket_names(&config.bucket_names);
+        let mut uploads: Vec<Upload> = Vec::new();
         while let Ok(Some(mut field)) = payload.try_next().await {
-            let content_type = field.content_disposition();
-            let user_dir = UserDir::new(&config, user_data);
-            let bucket = Bucket::new(&user_dir, None).unwrap();
-            let storage_file =
-                StorageFile::new(&bucket, content_type.get_filename().unwrap().into());
+            let content_type = field.content_disposition().clone();
+            let filename = if let Some(filename) = content_type.get_filename() {
+                filename
+            } else {
+                match content_type.get_name() {
+                    Some("expires") if !uploads.is_empty() => {
+                        return Err(ErrorBadRequest("The expiry must be sent before the files"));
+                    }
+                    Some("expires") => {
+                        expires = parse_expires(&read_text_field(&mut field).await?)?;
+                    }
+                    Some("bucket") if !uploads.is_empty() => {
+                        return Err(ErrorBadRequest("The bucket must be sent before the files"));
+                    }
+                    Some("bucket") => {
+                        bucket_name = Some(read_text_field(&mut field).await?.trim().to_string());
+                    }
+                    _ => {}
+                }
+                continue;
+            };
+            // Checked before creating the bucket, which would be left behind empty.
+            if StorageFile::sanitize(filename).is_empty() {
+                return Err(ErrorBadRequest("Invalid filename"));
+            }
+            let bucket = match &group {
+                Some(name) => Bucket::new(&user_dir, Some(name.clone()))
+                    .ok_or_else(|| ErrorBadRequest("Invalid bucket name"))?,
+                None => {
+                    let custom = bucket_name.is_some();
+                    let bucket = new_bucket(&user_dir, bucket_name.take()).await?;
+                    if custom || config.group_uploads(user_data) {
+                        group = Some(bucket.name.clone());
+                    }
+                    bucket
+                }
+            };
+            let storage_file = StorageFile::new(&bucket, filename.into());
+            if storage_file.exists().await {
+                return Err(ErrorConflict("File already exists"));
+            }
 
             println!(
                 "Uploading File to: {}/{}/{}",
                 user_data.folder, &bucket.name, &storage_file.name
             );
-            storage_file.write(&mut field).await?;
+            let expires = expires_at(expires);
+            let meta = FileMeta {
+                expires,
+                uploader: Some(username.to_string()),
+                original_name: Some(filename.to_string()),
+                content_type: field
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|content_type| content_type.to_str().ok())
+                    .map(str::to_string),
+                ..FileMeta::default()
+            };
+            let (written, metadata_stripped) =
+                write_upload(&config, user_data, &storage_file, &mut field, meta).await?;
 
-            files.push(format!(
-                "{}/{}/{}",
-                encode(&*user_data.folder),
-                encode(&*bucket.name),
-                encode(&*storage_file.name)
-            ));
+            let mut upload = uploaded(&config, username, &bucket, &storage_file, written, expires);
+            upload.metadata_stripped = metadata_stripped;
+            uploads.push(upload);
+        }
+
+        let mut response = HttpResponse::Ok();
+        response.insert_header((
+            "Deletion-Url",
+            uploads
+                .iter()
+                .map(|upload| upload.deletion_path.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+        ));
+        if wants_json(&request, &format) {
+            Ok(response.json(uploads))
+        } else {
+            Ok(response.body(
+                uploads
+                    .iter()
+                    .map(|upload| upload.path.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ))
         }
-        Ok(HttpResponse::Ok().body(files.join(",")))
     } else {
         Ok(HttpResponse::Forbidden().finish())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    mod test_find_file {
-        use crate::{find_file, Bucket, Config, StorageFile, UserDir};
-        use actix_web::http::StatusCode;
-        use actix_web::{test, web, App};
-        use std::io::Write;
+#[put("/{filename}")]
+async fn put_file(
+    path: web::Path<String>,
+    payload: web::Payload,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    format: Query<FormatQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    put_upload(
+        None,
+        path.into_inner(),
+        payload,
+        config,
+        backend,
+        format,
+        request,
+    )
+    .await
+}
 
-        #[actix_web::test]
-        async fn file_404() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(find_file),
-            )
-            .await;
+#[put("/{bucket}/{filename}")]
+async fn put_file_in_bucket(
+    path: web::Path<(String, String)>,
+    payload: web::Payload,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    format: Query<FormatQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (bucket, filename) = path.into_inner();
+    put_upload(
+        Some(bucket),
+        filename,
+        payload,
+        config,
+        backend,
+        format,
+        request,
+    )
+    .await
+}
 
-            let (user, _) = *(&config.users).iter().peekable().peek().unwrap();
-            let req = test::TestRequest::get()
-                .uri(&*format!("/{}/bucket/file.txt", user))
-                .to_request();
-            let resp = test::call_service(&app, req).await;
-            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
-        }
+/// Adds the extension of `content_type` to a filename without one, as files are served by their extension.
+/// Content types with several extensions only get the one named like their subtype, e.g. `image/jpeg`.
+fn with_extension(filename: String, content_type: Option<&str>) -> String {
+    let content_type =
+        match content_type.and_then(|content_type| content_type.parse::<mime::Mime>().ok()) {
+            Some(content_type) if content_type != mime::APPLICATION_OCTET_STREAM => content_type,
+            _ => return filename,
+        };
+    if Path::new(&filename).extension().is_some() {
+        return filename;
+    }
 
-        #[actix_web::test]
-        async fn user_404() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(find_file),
-            )
-            .await;
+    let extension = mime_guess::get_mime_extensions(&content_type).and_then(|extensions| {
+        extensions
+            .iter()
+            .find(|extension| **extension == content_type.subtype().as_str())
+            .or_else(|| extensions.first().filter(|_| extensions.len() == 1))
+    });
+    match extension {
+        Some(extension) => format!("{}.{}", filename, extension),
+        None => filename,
+    }
+}
 
-            let req = test::TestRequest::get()
-                .uri("/whothat/bucket/file.txt")
-                .to_request();
+/// Stores the raw request body as `filename`, in a new bucket unless `bucket` is given.
+async fn put_upload(
+    bucket: Option<String>,
+    filename: String,
+    mut payload: web::Payload,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    format: Query<FormatQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(Authed {
+        username,
+        user_data,
+        ..
+    }) = authed
+    {
+        // Refuse what is too large before receiving it, `write` checks again while streaming.
+        let limits = config.limits(user_data);
+        let content_length = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse::<u64>().ok());
+        if let (Some(length), Some(max)) = (content_length, limits.max_file_size) {
+            if length > max {
+                return Err(ErrorPayloadTooLarge("File too large"));
+            }
+        }
+
+        let expires = expires_at(requested_expiry(&request, user_data)?);
+        let user_dir = UserDir::new(backend.get_ref(), user_data)
+            .with_limits(limits)
+            .with_dedup(config.dedup)
+            .with_bucket_names(&config.bucket_names);
+        let content_type = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok());
+        let name = with_extension(filename.clone(), content_type);
+        // Checked before creating the bucket, which would be left behind empty.
+        if StorageFile::sanitize(&name).is_empty() {
+            return Err(ErrorBadRequest("Invalid filename"));
+        }
+        let bucket = match bucket {
+            Some(bucket) => own_bucket(&user_dir, bucket).await?,
+            None => Bucket::random(&user_dir).await?,
+        };
+        let storage_file = StorageFile::new(&bucket, name);
+        if storage_file.exists().await {
+            return Err(ErrorConflict("File already exists"));
+        }
+
+        println!(
+            "Uploading File to: {}/{}/{}",
+            user_data.folder, &bucket.name, &storage_file.name
+        );
+        let meta = FileMeta {
+            expires,
+            uploader: Some(username.to_string()),
+            original_name: Some(filename),
+            content_type: content_type.map(str::to_string),
+            ..FileMeta::default()
+        };
+        let (written, metadata_stripped) =
+            write_upload(&config, user_data, &storage_file, &mut payload, meta).await?;
+
+        let mut upload = uploaded(&config, username, &bucket, &storage_file, written, expires);
+        upload.metadata_stripped = metadata_stripped;
+        let mut response = HttpResponse::Ok();
+        response.insert_header(("Deletion-Url", upload.deletion_path.as_str()));
+        if wants_json(&request, &format) {
+            Ok(response.json(upload))
+        } else {
+            Ok(response.body(upload.path))
+        }
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+#[post("/paste")]
+async fn paste_text(
+    mut payload: web::Payload,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    query: Query<PasteQuery>,
+    format: Query<FormatQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(Authed {
+        username,
+        user_data,
+        ..
+    }) = authed
+    {
+        let content_type = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok());
+        let is_form = is_form(&request);
+        let (text, language) = if is_form {
+            let body = read_body(&mut payload, MAX_PASTE_FORM_SIZE).await?;
+            let form: PasteForm =
+                serde_urlencoded::from_bytes(&body).map_err(|_| ErrorBadRequest("Invalid form"))?;
+            (
+                Some(form.text),
+                form.language.or_else(|| query.language.clone()),
+            )
+        } else {
+            (None, query.language.clone())
+        };
+        let language = language.filter(|language| !language.trim().is_empty());
+        let extension = match &language {
+            Some(language) => paste::extension(language.trim())
+                .ok_or_else(|| ErrorBadRequest("Unknown language"))?,
+            None => "txt",
+        };
+
+        let expires = expires_at(requested_expiry(&request, user_data)?);
+        let user_dir = UserDir::new(backend.get_ref(), user_data)
+            .with_limits(config.limits(user_data))
+            .with_dedup(config.dedup)
+            .with_bucket_names(&config.bucket_names);
+        let bucket = new_bucket(&user_dir, requested_bucket(&request)?).await?;
+        let storage_file = StorageFile::new(&bucket, format!("paste.{}", extension));
+
+        println!(
+            "Pasting Text to: {}/{}/{}",
+            user_data.folder, &bucket.name, &storage_file.name
+        );
+        let meta = FileMeta {
+            expires,
+            uploader: Some(username.to_string()),
+            content_type: if is_form {
+                None
+            } else {
+                content_type.map(str::to_string)
+            },
+            language: language.map(|language| language.trim().to_string()),
+            ..FileMeta::default()
+        };
+        let written = match text {
+            Some(text) => {
+                storage_file
+                    .write(
+                        futures::stream::iter([Ok::<_, Error>(Bytes::from(text))]),
+                        meta,
+                    )
+                    .await?
+            }
+            None => storage_file.write(&mut payload, meta).await?,
+        };
+
+        let upload = uploaded(&config, username, &bucket, &storage_file, written, expires);
+        let mut response = HttpResponse::Ok();
+        response.insert_header(("Deletion-Url", upload.deletion_path.as_str()));
+        if wants_json(&request, &format) {
+            Ok(response.json(upload))
+        } else {
+            Ok(response.body(format!("{}/view", upload.path)))
+        }
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+#[post("/shorten")]
+async fn shorten_url(
+    mut payload: web::Payload,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    query: Query<ShortenForm>,
+    format: Query<FormatQuery>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(Authed {
+        username,
+        user_data,
+        ..
+    }) = authed
+    {
+        let body = read_body(&mut payload, MAX_LINK_BODY_SIZE).await?;
+        let form = if is_form(&request) {
+            serde_urlencoded::from_bytes(&body).map_err(|_| ErrorBadRequest("Invalid form"))?
+        } else {
+            ShortenForm {
+                url: Some(String::from_utf8(body).map_err(|_| ErrorBadRequest("Invalid url"))?),
+                expires: None,
+            }
+        };
+        let target = form
+            .url
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .or_else(|| query.url.clone())
+            .ok_or_else(|| ErrorBadRequest("Missing url"))?;
+        let uri: Uri = target.parse().map_err(|_| ErrorBadRequest("Invalid url"))?;
+        if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.host().is_none() {
+            return Err(ErrorBadRequest("Only http and https urls can be shortened"));
+        }
+
+        let expires = match form.expires.or_else(|| query.expires.clone()) {
+            Some(expires) => parse_expires(&expires)?,
+            None => requested_expiry(&request, user_data)?,
+        };
+        let expires = expires_at(expires);
+        let user_dir = UserDir::new(backend.get_ref(), user_data)
+            .with_limits(config.limits(user_data))
+            .with_dedup(config.dedup)
+            .with_bucket_names(&config.bucket_names);
+        let bucket = new_bucket(&user_dir, requested_bucket(&request)?).await?;
+        let storage_file = StorageFile::new(&bucket, LINK_FILE.to_string());
+
+        println!(
+            "Shortening Url to: {}/{}/{}",
+            user_data.folder, &bucket.name, &storage_file.name
+        );
+        let id = shortlink::create(
+            backend.get_ref(),
+            &LinkTarget {
+                username: username.to_string(),
+                bucket: bucket.name.clone(),
+            },
+        )
+        .await?;
+        let meta = FileMeta {
+            expires,
+            uploader: Some(username.to_string()),
+            redirect: Some(target.clone()),
+            short_link: Some(id.clone()),
+            ..FileMeta::default()
+        };
+        let written = match storage_file
+            .write(
+                futures::stream::iter([Ok::<_, Error>(Bytes::from(target.clone()))]),
+                meta,
+            )
+            .await
+        {
+            Ok(written) => written,
+            Err(err) => {
+                shortlink::delete(backend.get_ref(), &id).await.ok();
+                return Err(err);
+            }
+        };
+
+        let link = uploaded(&config, username, &bucket, &storage_file, written, expires);
+        let mut response = HttpResponse::Ok();
+        response.insert_header(("Deletion-Url", link.deletion_path.as_str()));
+        let path = format!("s/{}", id);
+        if wants_json(&request, &format) {
+            Ok(response.json(ShortLink {
+                url: format!("{}/{}", config.public_url(), path),
+                target,
+                deletion_url: link.deletion_url,
+                expires,
+            }))
+        } else {
+            Ok(response.body(path))
+        }
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+/// Announces the largest upload of the authenticated user, or the global one for anonymous requests.
+#[options("/tus")]
+async fn tus_options(config: web::Data<Config>, request: HttpRequest) -> HttpResponse {
+    let mut response = HttpResponse::NoContent();
+    response
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", tus::TUS_EXTENSIONS));
+    let max_file_size = match is_authed_header(config.get_ref(), &request) {
+        Ok(Some(Authed { user_data, .. })) => config.limits(user_data).max_file_size,
+        _ => config.max_file_size,
+    };
+    if let Some(max_file_size) = max_file_size {
+        response.insert_header(("Tus-Max-Size", max_file_size.to_string()));
+    }
+    response.finish()
+}
+
+#[post("/tus", wrap = "tus_headers()")]
+async fn tus_create(
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    tus_resumable(&request)?;
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(Authed {
+        username,
+        user_data,
+        ..
+    }) = authed
+    {
+        let length = tus_number(&request, "Upload-Length")?;
+        let metadata = match request.headers().get("Upload-Metadata") {
+            Some(metadata) => metadata
+                .to_str()
+                .ok()
+                .and_then(tus::parse_metadata)
+                .ok_or_else(|| ErrorBadRequest("Invalid Upload-Metadata"))?,
+            None => HashMap::new(),
+        };
+        if let Some(expires) = metadata.get("expires") {
+            parse_expires(expires)?;
+        }
+        // Checked now, a file without a usable name could never be finished.
+        if metadata
+            .get("filename")
+            .is_some_and(|filename| StorageFile::sanitize(filename).is_empty())
+        {
+            return Err(ErrorBadRequest("Invalid filename"));
+        }
+
+        let limits = config.limits(user_data);
+        if limits.max_file_size.is_some_and(|max| length > max) {
+            return Err(ErrorPayloadTooLarge("File too large"));
+        }
+        // Held until the upload is complete, terminated or expired, so the parts count against the quota.
+        let user_dir = UserDir::new(backend.get_ref(), user_data).with_limits(limits);
+        user_dir.reserve(length).await?;
+
+        let upload = TusUpload::new(backend.get_ref(), None).unwrap();
+        let info = TusInfo {
+            username: username.to_string(),
+            folder: user_data.folder.clone(),
+            length,
+            metadata,
+            expires: expires_at(Some(config.tus_expiration)).unwrap(),
+            bucket: None,
+            url: None,
+            deletion_url: None,
+            metadata_stripped: false,
+        };
+        println!("Creating Upload: {} of {} bytes", upload.id, length);
+        if let Err(err) = upload.create(&info).await {
+            user_dir.release(length);
+            return Err(err.into());
+        }
+
+        Ok(HttpResponse::Created()
+            .insert_header((
+                header::LOCATION,
+                format!("{}/tus/{}", config.public_url(), upload.id),
+            ))
+            .insert_header(("Upload-Expires", http_date(info.expires)))
+            .finish())
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+#[head("/tus/{id}", wrap = "tus_headers()")]
+async fn tus_offset(
+    path: web::Path<String>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    tus_resumable(&request)?;
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(Authed { username, .. }) = authed {
+        let (upload, info) = tus_upload(backend.get_ref(), path.into_inner(), username).await?;
+        let mut response = HttpResponse::Ok();
+        response
+            .insert_header(("Upload-Offset", upload.offset(&info).await?.to_string()))
+            .insert_header(("Upload-Length", info.length.to_string()))
+            .insert_header(("Upload-Expires", http_date(info.expires)))
+            .insert_header((header::CACHE_CONTROL, "no-store"));
+        if !info.metadata.is_empty() {
+            response.insert_header(("Upload-Metadata", tus::format_metadata(&info.metadata)));
+        }
+        if let (Some(url), Some(deletion_url)) = (info.url, info.deletion_url) {
+            response
+                .insert_header(("File-Url", url))
+                .insert_header(("Deletion-Url", deletion_url))
+                .insert_header(("Metadata-Stripped", info.metadata_stripped.to_string()));
+        }
+
+        Ok(response.finish())
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+#[patch("/tus/{id}", wrap = "tus_headers()")]
+async fn tus_append(
+    path: web::Path<String>,
+    mut payload: web::Payload,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    tus_resumable(&request)?;
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(Authed {
+        username,
+        user_data,
+        ..
+    }) = authed
+    {
+        let (upload, mut info) = tus_upload(backend.get_ref(), path.into_inner(), username).await?;
+        if request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            != Some("application/offset+octet-stream")
+        {
+            return Err(ErrorUnsupportedMediaType(
+                "Content-Type must be application/offset+octet-stream",
+            ));
+        }
+        let guard = upload
+            .lock()
+            .ok_or_else(|| ErrorLocked("Upload is already being appended to"))?;
+        let offset = upload.offset(&info).await?;
+        if tus_number(&request, "Upload-Offset")? != offset {
+            return Err(ErrorConflict("Upload-Offset does not match"));
+        }
+
+        let offset = if info.is_complete() {
+            offset
+        } else {
+            upload
+                .append(&guard, offset, info.length, &mut payload)
+                .await?
+        };
+        // An empty `PATCH` at the end retries the move into a bucket, should it have failed before.
+        if offset == info.length && info.url.is_none() {
+            let filename = info
+                .metadata
+                .get("filename")
+                .filter(|filename| !StorageFile::sanitize(filename).is_empty())
+                .cloned()
+                .unwrap_or_else(|| "file".to_string());
+            let expires = expires_at(match info.metadata.get("expires") {
+                Some(expires) => parse_expires(expires)?,
+                None => user_data.expire_after,
+            });
+            // The quota already counts the reserved length, which is given back once the file is recorded.
+            let limits = config.limits(user_data);
+            let user_dir = UserDir::new(backend.get_ref(), user_data)
+                .with_limits(Limits {
+                    quota_bytes: limits
+                        .quota_bytes
+                        .map(|quota| quota.saturating_add(info.length)),
+                    ..limits
+                })
+                .with_dedup(config.dedup)
+                .with_bucket_names(&config.bucket_names);
+            // Recorded before the content is moved, so a retry finishes into the same file instead of another one.
+            let bucket = match &info.bucket {
+                Some(bucket) => Bucket::new(&user_dir, Some(bucket.clone()))
+                    .ok_or_else(|| ErrorInternalServerError("Invalid bucket"))?,
+                None => {
+                    let bucket = Bucket::random(&user_dir).await?;
+                    info.bucket = Some(bucket.name.clone());
+                    upload.write_info(&info).await?;
+                    bucket
+                }
+            };
+            let storage_file = StorageFile::new(&bucket, filename.clone());
+
+            let (written, metadata_stripped) = if storage_file.exists().await {
+                // Written by a previous attempt, which failed before recording the url.
+                let meta = storage_file.meta().await;
+                let written = Written {
+                    size: meta.size.unwrap_or_default(),
+                    sha256: meta.sha256.unwrap_or_default(),
+                    created: meta.created.unwrap_or_default(),
+                };
+                // Only removing metadata changes the size.
+                let metadata_stripped = written.size != info.length;
+                (written, metadata_stripped)
+            } else {
+                println!(
+                    "Finishing Upload {} to: {}/{}/{}",
+                    upload.id, user_data.folder, &bucket.name, &storage_file.name
+                );
+                let meta = FileMeta {
+                    expires,
+                    uploader: Some(username.to_string()),
+                    original_name: Some(filename),
+                    content_type: info.metadata.get("filetype").cloned(),
+                    ..FileMeta::default()
+                };
+                let stripping =
+                    config.strip_metadata(user_data) && strip::is_supported(&storage_file.name);
+                match upload.single_part().await? {
+                    Some(part) if !stripping => {
+                        (storage_file.write_moved(&part, meta).await?, false)
+                    }
+                    _ => {
+                        write_upload(
+                            &config,
+                            user_data,
+                            &storage_file,
+                            upload.content().await?,
+                            meta,
+                        )
+                        .await?
+                    }
+                }
+            };
+
+            let file = uploaded(&config, username, &bucket, &storage_file, written, expires);
+            info.url = Some(file.url);
+            info.deletion_url = Some(file.deletion_url);
+            info.metadata_stripped = metadata_stripped;
+            upload.write_info(&info).await?;
+            user_dir.release(info.length);
+            upload.delete_parts().await?;
+        }
+
+        let mut response = HttpResponse::NoContent();
+        response
+            .insert_header(("Upload-Offset", offset.to_string()))
+            .insert_header(("Upload-Expires", http_date(info.expires)));
+        if let (Some(url), Some(deletion_url)) = (info.url, info.deletion_url) {
+            response
+                .insert_header(("File-Url", url))
+                .insert_header(("Deletion-Url", deletion_url))
+                .insert_header(("Metadata-Stripped", info.metadata_stripped.to_string()));
+        }
+
+        Ok(response.finish())
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+/// Cancels a resumable upload, a file it completed into is kept.
+#[delete("/tus/{id}", wrap = "tus_headers()")]
+async fn tus_terminate(
+    path: web::Path<String>,
+    config: web::Data<Config>,
+    backend: web::Data<dyn StorageBackend>,
+    request: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    tus_resumable(&request)?;
+    let authed = is_authed_header(config.get_ref(), &request)?
+        .filter(|authed| authed.api_key.has_scope(Scope::Upload));
+    if let Some(Authed {
+        username,
+        user_data,
+        ..
+    }) = authed
+    {
+        let (upload, info) = tus_upload(backend.get_ref(), path.into_inner(), username).await?;
+        let _guard = upload
+            .lock()
+            .ok_or_else(|| ErrorLocked("Upload is being appended to"))?;
+
+        println!("Terminating Upload: {}", upload.id);
+        upload.delete().await?;
+        if info.url.is_none() {
+            UserDir::new(backend.get_ref(), user_data).release(info.length);
+        }
+
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Ok(HttpResponse::Forbidden().finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::BackendKind;
+    use crate::store::Written;
+    use crate::{Config, FileMeta, StorageFile};
+    use actix_web::web::Bytes;
+
+    /// The default config, but storing everything in memory.
+    pub fn memory_config() -> Config {
+        Config {
+            backend: BackendKind::Memory,
+            ..Config::default()
+        }
+    }
+
+    /// Writes `This is a testfile!` to `storage_file`.
+    pub async fn write_test_file(storage_file: &StorageFile<'_, '_, '_, '_>) -> Written {
+        storage_file
+            .write(
+                futures::stream::iter([Ok::<_, actix_web::Error>(Bytes::from_static(
+                    b"This is a testfile!",
+                ))]),
+                FileMeta::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    /// Initializes an app serving `services` with the given config and backend.
+    macro_rules! init_app {
+        ($config:expr, $backend:expr, $($service:expr),+ $(,)?) => {
+            actix_web::test::init_service(
+                actix_web::App::new()
+                    .app_data(actix_web::web::Data::new($config.clone()))
+                    .app_data(actix_web::web::Data::from($backend.clone()))
+                    $(.service($service))+,
+            )
+            .await
+        };
+    }
+
+    mod test_find_file {
+        use crate::backend::open_backend;
+        use crate::tests::{memory_config, write_test_file};
+        use crate::{find_file, Bucket, FileMeta, StorageFile, UserDir};
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test;
+
+        #[actix_web::test]
+        async fn file_404() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, find_file);
+
+            let (user, _) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::get()
+                .uri(&format!("/{}/bucket/file.txt", user))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+
+        #[actix_web::test]
+        async fn bucket_404() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, find_file);
+
+            let (user, _) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::get()
+                .uri(&format!("/{}/buck-et/file.txt", user))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+
+        #[actix_web::test]
+        async fn user_404() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, find_file);
+
+            let req = test::TestRequest::get()
+                .uri("/whothat/bucket/file.txt")
+                .to_request();
             let resp = test::call_service(&app, req).await;
             assert_eq!(resp.status(), StatusCode::NOT_FOUND);
         }
 
         #[actix_web::test]
         async fn file_200() {
-            let config = Config::default();
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, find_file);
+
+            let filename = "file.txt";
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, None).unwrap();
+            let storage_file = StorageFile::new(&bucket, filename.into());
+            write_test_file(&storage_file).await;
+
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/{}/{}/{}",
+                    user, &bucket.name, &storage_file.name
+                ))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            storage_file.delete().await.unwrap();
+
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_SECURITY_POLICY).unwrap(),
+                "sandbox"
+            );
+            assert_eq!(
+                resp.headers().get(header::X_CONTENT_TYPE_OPTIONS).unwrap(),
+                "nosniff"
+            );
+        }
+
+        #[actix_web::test]
+        async fn file_404_expired() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, find_file);
+
+            let filename = "file.txt";
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, None).unwrap();
+            let storage_file = StorageFile::new(&bucket, filename.into());
+            write_test_file(&storage_file).await;
+            storage_file
+                .write_meta(&FileMeta {
+                    expires: Some(1),
+                    ..FileMeta::default()
+                })
+                .await
+                .unwrap();
+
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/{}/{}/{}",
+                    user, &bucket.name, &storage_file.name
+                ))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert!(storage_file.exists().await);
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+
+        #[actix_web::test]
+        async fn file_206() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, find_file);
+
+            let filename = "file.txt";
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, None).unwrap();
+            let storage_file = StorageFile::new(&bucket, filename.into());
+            write_test_file(&storage_file).await;
+
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/{}/{}/{}",
+                    user, &bucket.name, &storage_file.name
+                ))
+                .insert_header(("range", "bytes=5-8"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(resp.headers().get("content-range").unwrap(), "bytes 5-8/19");
+            assert_eq!(test::read_body(resp).await, "is a");
+        }
+    }
+
+    mod test_get_delete_file {
+        use crate::backend::open_backend;
+        use crate::tests::{memory_config, write_test_file};
+        use crate::token::deletion_token;
+        use crate::{get_delete_file, services, Bucket, FileMeta, StorageFile, UserDir};
+        use actix_web::http::StatusCode;
+        use actix_web::web::{self, Bytes};
+        use actix_web::{test, App};
+
+        #[actix_web::test]
+        async fn file_200() {
+            let mut config = memory_config();
+            // Links name the user, which does not have to be the name of their folder.
+            config.users.values_mut().next().unwrap().folder = "files".to_string();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, get_delete_file);
+
+            let filename = "file.txt";
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, None).unwrap();
+            let storage_file = StorageFile::new(&bucket, filename.into());
+            let written = write_test_file(&storage_file).await;
+
+            let path = format!("{}/{}/{}", user, &bucket.name, &storage_file.name);
+            let uri = format!(
+                "/delete/{}?token={}",
+                path,
+                deletion_token(&config.secret, &path, written.created, &written.sha256)
+            );
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert!(!storage_file.exists().await);
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            // The token does not delete a later upload to the same path.
+            storage_file
+                .write(
+                    futures::stream::iter([Ok::<_, actix_web::Error>(Bytes::from_static(
+                        b"Another testfile!",
+                    ))]),
+                    FileMeta::default(),
+                )
+                .await
+                .unwrap();
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            assert!(storage_file.exists().await);
+        }
+
+        #[actix_web::test]
+        async fn file_named_thumb_200() {
+            let config = memory_config();
+            let backend = open_backend(&config);
             let app = test::init_service(
                 App::new()
                     .app_data(web::Data::new(config.clone()))
-                    .service(find_file),
+                    .app_data(web::Data::from(backend.clone()))
+                    .configure(services),
             )
             .await;
 
-            let filename = "file.txt";
-            let (user, user_data) = *(&config.users).iter().peekable().peek().unwrap();
-            let user_dir = UserDir::new(&config, user_data);
-            let bucket = Bucket::new(&user_dir, None).unwrap();
-            let storage_file = StorageFile::new(&bucket, filename.into());
-            {
-                let mut file = storage_file.open(true).await.unwrap();
-                file = web::block(move || file.write_all(b"This is a testfile!").map(|_| file))
-                    .await
-                    .unwrap()
-                    .unwrap();
-                web::block(move || file.flush()).await.unwrap().unwrap();
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some("bucket".into())).unwrap();
+            for filename in ["thumb", "view"] {
+                let storage_file = StorageFile::new(&bucket, filename.into());
+                let written = write_test_file(&storage_file).await;
+
+                let path = format!("{}/bucket/{}", user, filename);
+                let uri = format!(
+                    "/delete/{}?token={}",
+                    path,
+                    deletion_token(&config.secret, &path, written.created, &written.sha256)
+                );
+                let resp =
+                    test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+                assert_eq!(resp.status(), StatusCode::OK);
+                assert!(!storage_file.exists().await);
             }
+        }
+
+        #[actix_web::test]
+        async fn file_404() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, get_delete_file);
+
+            let (user, _) = *config.users.iter().peekable().peek().unwrap();
+            let path = format!("{}/bucket/file.txt", user);
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/delete/{}?token={}",
+                    path,
+                    deletion_token(&config.secret, &path, 0, "")
+                ))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+
+        #[actix_web::test]
+        async fn file_403_token() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, get_delete_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some("bucket".into())).unwrap();
+            let storage_file = StorageFile::new(&bucket, "file.txt".into());
+            let written = write_test_file(&storage_file).await;
+
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/delete/{}/bucket/file.txt?token={}",
+                    user,
+                    deletion_token(
+                        &config.secret,
+                        &format!("{}/bucket/other.txt", user),
+                        written.created,
+                        &written.sha256
+                    )
+                ))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            assert!(storage_file.exists().await);
+        }
+
+        #[actix_web::test]
+        async fn file_403_user() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, get_delete_file);
+
+            let path = "someone/bucket/file.txt";
+            let req = test::TestRequest::get()
+                .uri(&format!(
+                    "/delete/{}?token={}",
+                    path,
+                    deletion_token(&config.secret, path, 0, "")
+                ))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+
+        #[actix_web::test]
+        async fn file_400_token() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, get_delete_file);
+
+            let (user, _) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::get()
+                .uri(&format!("/delete/{}/bucket/file.txt", user))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    mod test_delete_file {
+        use crate::backend::open_backend;
+        use crate::tests::{memory_config, write_test_file};
+        use crate::{delete_file, Bucket, StorageFile, UserDir};
+        use actix_web::http::StatusCode;
+        use actix_web::test;
+
+        #[actix_web::test]
+        async fn file_200() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, delete_file);
+
+            let filename = "file.txt";
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, None).unwrap();
+            let storage_file = StorageFile::new(&bucket, filename.into());
+            write_test_file(&storage_file).await;
+
+            let req = test::TestRequest::delete()
+                .uri(&format!("/{}/{}", &bucket.name, &storage_file.name))
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert!(!storage_file.exists().await);
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        #[actix_web::test]
+        async fn file_404() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, delete_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::delete()
+                .uri("/bucket/file.txt")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        }
+
+        #[actix_web::test]
+        async fn file_403() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, delete_file);
+
+            let req = test::TestRequest::delete()
+                .uri("/bucket/file.txt")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+
+        #[actix_web::test]
+        async fn file_403_user() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, delete_file);
+
+            let (user, _) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::delete()
+                .uri("/bucket/file.txt")
+                .insert_header(("username", user.clone()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+
+        #[actix_web::test]
+        async fn file_403_auth() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, delete_file);
+
+            let (_, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::delete()
+                .uri("/bucket/file.txt")
+                .insert_header(("auth", user_data.key.clone()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    mod test_list_files {
+        use crate::backend::open_backend;
+        use crate::tests::memory_config;
+        use crate::{list_files, Bucket, FileMeta, StorageFile, UserDir};
+        use actix_web::http::StatusCode;
+        use actix_web::test;
+        use actix_web::web::Bytes;
+
+        #[actix_web::test]
+        async fn files_200() {
+            let mut config = memory_config();
+            // Links name the user, which does not have to be the name of their folder.
+            config.users.values_mut().next().unwrap().folder = "files".to_string();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, list_files);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            for (filename, content) in [
+                ("notes.txt", &b"This is a testfile!"[..]),
+                ("screenshot.png", &b"PNG"[..]),
+                ("photo.png", &b"Larger PNG"[..]),
+            ] {
+                let bucket = Bucket::new(&user_dir, None).unwrap();
+                StorageFile::new(&bucket, filename.into())
+                    .write(
+                        futures::stream::iter([Ok::<_, actix_web::Error>(Bytes::from(content))]),
+                        FileMeta::default(),
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            let list = |query: &str| {
+                test::TestRequest::get()
+                    .uri(&format!("/api/files{}", query))
+                    .insert_header(("username", user.clone()))
+                    .insert_header(("auth", user_data.key.clone()))
+                    .to_request()
+            };
+            let filenames = |body: &serde_json::Value| {
+                body["files"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|file| file["filename"].as_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            };
+
+            let body: serde_json::Value =
+                test::call_and_read_body_json(&app, list("?sort=size&order=asc")).await;
+            assert_eq!(body["total"], 3);
+            assert_eq!(
+                filenames(&body),
+                vec!["screenshot.png", "photo.png", "notes.txt"]
+            );
+            let file = &body["files"][0];
+            assert_eq!(file["size"], 3);
+            assert_eq!(file["content_type"], "image/png");
+            assert_eq!(
+                file["thumbnail_url"],
+                format!("{}/thumb", file["url"].as_str().unwrap())
+            );
+            assert_eq!(
+                file["url"],
+                format!(
+                    "{}/{}/{}/screenshot.png",
+                    config.public_url(),
+                    user,
+                    file["bucket"].as_str().unwrap()
+                )
+            );
+
+            let body: serde_json::Value =
+                test::call_and_read_body_json(&app, list("?name=*shot*")).await;
+            assert_eq!(filenames(&body), vec!["screenshot.png"]);
+
+            let body: serde_json::Value =
+                test::call_and_read_body_json(&app, list("?content_type=image/*&sort=name")).await;
+            assert_eq!(filenames(&body), vec!["screenshot.png", "photo.png"]);
+
+            let body: serde_json::Value =
+                test::call_and_read_body_json(&app, list("?sort=name&per_page=2&page=2")).await;
+            assert_eq!(body["total"], 3);
+            assert_eq!(filenames(&body), vec!["notes.txt"]);
+
+            let resp = test::call_service(&app, list("?sort=color")).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        #[actix_web::test]
+        async fn files_403() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, list_files);
+
+            let req = test::TestRequest::get().uri("/api/files").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+            let (user, _) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::get()
+                .uri("/api/files")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", "wrong"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+    }
+
+    mod test_bucket_index {
+        use crate::backend::open_backend;
+        use crate::tests::memory_config;
+        use crate::{bucket_index, download_bucket, Bucket, FileMeta, StorageFile, UserDir};
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test;
+        use actix_web::web::Bytes;
+
+        #[actix_web::test]
+        async fn index_200_404() {
+            let mut config = memory_config();
+            // Links name the user, which does not have to be the name of their folder.
+            config.users.values_mut().next().unwrap().folder = "files".to_string();
+            let backend = open_backend(&config);
+            let (user, user_data) = config
+                .users
+                .iter()
+                .next()
+                .map(|(user, user_data)| (user.clone(), user_data.clone()))
+                .unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), &user_data);
+            let bucket = Bucket::new(&user_dir, Some("holiday".into())).unwrap();
+            for (filename, content) in [
+                ("notes.txt", &b"This is a testfile!"[..]),
+                ("beach.png", &b"PNG"[..]),
+            ] {
+                StorageFile::new(&bucket, filename.into())
+                    .write(
+                        futures::stream::iter([Ok::<_, actix_web::Error>(Bytes::from(content))]),
+                        FileMeta::default(),
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            let app = init_app!(config, backend, download_bucket, bucket_index);
+            let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+            let resp = test::call_service(&app, get(format!("/{}/holiday", user))).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/html; charset=utf-8"
+            );
+            let html = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+            assert!(html.contains("<h1>holiday</h1>"));
+            assert!(html.contains(&format!(
+                r#"<img src="{}/{}/holiday/beach.png/thumb""#,
+                config.public_url(),
+                user
+            )));
+
+            let body: serde_json::Value = test::call_and_read_body_json(
+                &app,
+                test::TestRequest::get()
+                    .uri(&format!("/{}/holiday", user))
+                    .insert_header((header::ACCEPT, "application/json"))
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(body["bucket"], "holiday");
+            assert_eq!(body["size"], 22);
+            assert_eq!(body["files"][0]["filename"], "beach.png");
+            assert_eq!(body["files"][1]["filename"], "notes.txt");
+            assert_eq!(
+                body["download_url"],
+                format!("{}/{}/holiday.zip", config.public_url(), user)
+            );
+
+            for uri in [
+                format!("/{}/missing", user),
+                format!("/{}/hol-iday", user),
+                "/unknown/holiday".to_string(),
+            ] {
+                let resp = test::call_service(&app, get(uri)).await;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            }
+
+            config.users.get_mut(&user).unwrap().public_listing = Some(false);
+            let app = init_app!(config, backend, download_bucket, bucket_index);
+            for uri in [
+                format!("/{}/holiday", user),
+                format!("/{}/holiday.zip", user),
+            ] {
+                let resp = test::call_service(&app, get(uri)).await;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            }
+        }
+    }
+
+    mod test_download_bucket {
+        use crate::backend::open_backend;
+        use crate::tests::{memory_config, write_test_file};
+        use crate::{download_bucket, Bucket, StorageFile, UserDir};
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test;
+
+        #[actix_web::test]
+        async fn bucket_200_404() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, download_bucket);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some("holiday".into())).unwrap();
+            write_test_file(&StorageFile::new(&bucket, "notes.txt".into())).await;
+
+            let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+            let resp = test::call_service(&app, get(format!("/{}/holiday.zip", user))).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+                "attachment; filename=\"holiday.zip\""
+            );
+            let length: usize = resp
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            let body = test::read_body(resp).await;
+            assert_eq!(body.len(), length);
+            assert_eq!(&body[30..47], b"holiday/notes.txt");
+
+            let resp = test::call_service(&app, get(format!("/{}/holiday.tar.gz", user))).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "application/gzip"
+            );
+            assert!(!test::read_body(resp).await.is_empty());
+
+            for uri in [
+                format!("/{}/missing.zip", user),
+                "/unknown/holiday.zip".to_string(),
+            ] {
+                let resp = test::call_service(&app, get(uri)).await;
+                assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            }
+        }
+    }
+
+    mod test_pages {
+        use crate::{dashboard, upload_page};
+        use actix_web::http::{header, StatusCode};
+        use actix_web::{test, App};
+
+        #[actix_web::test]
+        async fn dashboard_200() {
+            let app = test::init_service(App::new().service(dashboard)).await;
+
+            let req = test::TestRequest::get().uri("/dashboard").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/html; charset=utf-8"
+            );
+            let body = test::read_body(resp).await;
+            assert!(std::str::from_utf8(&body).unwrap().contains("/api/files"));
+        }
+
+        #[actix_web::test]
+        async fn upload_page_200() {
+            let app = test::init_service(App::new().service(upload_page)).await;
+
+            let req = test::TestRequest::get().uri("/upload").to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/html; charset=utf-8"
+            );
+            let body = test::read_body(resp).await;
+            assert!(std::str::from_utf8(&body)
+                .unwrap()
+                .contains("/?format=json"));
+        }
+    }
+
+    mod test_upload_file {
+        use crate::backend::open_backend;
+        use crate::config::{BucketNames, NameStyle};
+        use crate::tests::memory_config;
+        use crate::token::deletion_token;
+        use crate::{upload_file, Bucket, Config, StorageFile, UserDir};
+        use actix_web::http::StatusCode;
+        use actix_web::test;
+        use actix_web::web::Bytes;
+        use futures::TryStreamExt;
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        const BOUNDARY: &str = "----boundary";
+
+        fn multipart(parts: &[(&str, Option<&str>, &str)]) -> String {
+            let mut body = String::new();
+            for (name, filename, content) in parts {
+                body.push_str(&format!("--{}\r\n", BOUNDARY));
+                match filename {
+                    Some(filename) => body.push_str(&format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\r\n",
+                        name, filename
+                    )),
+                    None => body.push_str(&format!(
+                        "Content-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                        name
+                    )),
+                }
+                body.push_str(&format!("{}\r\n", content));
+            }
+            body.push_str(&format!("--{}--\r\n", BOUNDARY));
+            body
+        }
+
+        #[actix_web::test]
+        async fn file_200() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[(
+                    "file",
+                    Some("file.txt"),
+                    "This is a testfile!",
+                )]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let deletion_url = resp.headers().get("deletion-url").unwrap().clone();
+            let body = test::read_body(resp).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            let path: Vec<&str> = body.split('/').collect();
+            assert_eq!(path[0], user);
+            assert_eq!(path[2], "file.txt");
+
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some(path[1].to_string())).unwrap();
+            let storage_file = StorageFile::new(&bucket, path[2].to_string());
+            assert!(storage_file.exists().await);
+            let meta = storage_file.meta().await;
+            let token_path = format!("{}/{}/file.txt", user, path[1]);
+            assert_eq!(
+                deletion_url,
+                format!(
+                    "delete/{}?token={}",
+                    token_path,
+                    deletion_token(
+                        &config.secret,
+                        &token_path,
+                        meta.created.unwrap(),
+                        meta.sha256.as_deref().unwrap()
+                    )
+                )
+                .as_str()
+            );
+            assert!(meta.expires.is_none());
+            assert_eq!(meta.uploader.as_deref(), Some(user.as_str()));
+            assert_eq!(meta.original_name.as_deref(), Some("file.txt"));
+            assert_eq!(meta.size, Some(19));
+            assert!(meta.content_type.is_none());
+        }
+
+        #[actix_web::test]
+        async fn file_413() {
+            let config = Config {
+                max_file_size: Some(10),
+                ..memory_config()
+            };
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[(
+                    "file",
+                    Some("file.txt"),
+                    "This is a testfile!",
+                )]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            assert!(user_dir.open(false).await.is_none());
+        }
+
+        #[actix_web::test]
+        async fn file_200_json() {
+            let mut config = Config {
+                public_url: Some("https://share.example.com/".to_string()),
+                ..memory_config()
+            };
+            // The links name the user, not their folder.
+            config.users.values_mut().next().unwrap().folder = "folder".to_string();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/?format=json")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[
+                    ("file", Some("file.txt"), "This is a testfile!"),
+                    ("image", Some("image.png"), "PNG"),
+                ]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let uploads: serde_json::Value = test::read_body_json(resp).await;
+            let upload = &uploads[0];
+            let url = upload["url"].as_str().unwrap();
+            let path = url.strip_prefix("https://share.example.com/").unwrap();
+            assert!(path.starts_with(&format!("{}/", user)));
+            assert!(path.ends_with("/file.txt"));
+            let bucket = path.split('/').nth(1).unwrap();
+            assert!(upload["deletion_url"]
+                .as_str()
+                .unwrap()
+                .starts_with(&format!(
+                    "https://share.example.com/delete/{}/{}/file.txt?token=",
+                    user, bucket
+                )));
+            assert_eq!(upload["thumbnail_url"], serde_json::Value::Null);
+            assert_eq!(upload["size"], 19);
+            assert_eq!(upload["content_type"], "text/plain");
+            assert_eq!(
+                upload["checksum"],
+                "2203f2f8e854923946eccbaca2f9ed70ecc6d94fca5f07232a91e488c319bd0b"
+            );
+            assert_eq!(upload["expires"], serde_json::Value::Null);
+            assert_eq!(
+                uploads[1]["thumbnail_url"],
+                format!("{}/thumb", uploads[1]["url"].as_str().unwrap())
+            );
+            assert_eq!(uploads[1]["content_type"], "image/png");
+        }
+
+        #[actix_web::test]
+        async fn file_200_strip_metadata() {
+            let config = Config {
+                strip_metadata: true,
+                ..memory_config()
+            };
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let chunks =
+                "VP8X\x0a\0\0\0\x0c\0\0\0\x0f\0\0\x0f\0\0VP8L\x03\0\0\0abc\0EXIF\x05\0\0\0GPS!!\0";
+            let webp = format!(
+                "RIFF{}\0\0\0WEBP{}",
+                char::from(4 + chunks.len() as u8),
+                chunks
+            );
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/?format=json")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[
+                    ("file", Some("photo.webp"), &webp),
+                    ("file", Some("notes.txt"), "GPS!!"),
+                ]))
+                .to_request();
+            let uploads: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(uploads[0]["metadata_stripped"], true);
+            assert_eq!(uploads[0]["size"], webp.len() - 14);
+            assert_eq!(uploads[1]["metadata_stripped"], false);
+
+            let path = uploads[0]["url"].as_str().unwrap();
+            let bucket_name = path.rsplit('/').nth(1).unwrap().to_string();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some(bucket_name)).unwrap();
+            let object = StorageFile::new(&bucket, "photo.webp".into())
+                .open(None)
+                .await
+                .unwrap();
+            let content: Vec<Bytes> = object.body.try_collect().await.unwrap();
+            let content = content.concat();
+            assert!(!content.windows(3).any(|window| window == b"GPS"));
+        }
+
+        #[actix_web::test]
+        async fn file_200_expires() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[
+                    ("expires", None, "1h"),
+                    ("file", Some("file.txt"), "This is a testfile!"),
+                ]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            let body = test::read_body(resp).await;
+            let path: Vec<&str> = std::str::from_utf8(&body).unwrap().split('/').collect();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some(path[1].to_string())).unwrap();
+            let storage_file = StorageFile::new(&bucket, path[2].to_string());
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let expires = storage_file.meta().await.expires.unwrap();
+            assert!(expires > now + 3500 && expires <= now + 3600);
+        }
+
+        #[actix_web::test]
+        async fn file_400_expires() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header(("expires", "soon"))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[(
+                    "file",
+                    Some("file.txt"),
+                    "This is a testfile!",
+                )]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        #[actix_web::test]
+        async fn file_400_filename() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[
+                    ("bucket", None, "holiday2024"),
+                    ("file", Some(".."), "This is a testfile!"),
+                ]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some("holiday2024".into())).unwrap();
+            assert!(bucket.open(false).await.is_none());
+        }
+
+        #[actix_web::test]
+        async fn file_400_late_expires() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[
+                    ("file", Some("file.txt"), "This is a testfile!"),
+                    ("expires", None, "1h"),
+                ]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        #[actix_web::test]
+        async fn file_200_bucket() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let upload = |bucket_header: Option<&str>, parts: &[(&str, Option<&str>, &str)]| {
+                let mut req = test::TestRequest::post()
+                    .uri("/")
+                    .insert_header(("username", user.clone()))
+                    .insert_header(("auth", user_data.key.clone()))
+                    .insert_header((
+                        "content-type",
+                        format!("multipart/form-data; boundary={}", BOUNDARY),
+                    ))
+                    .set_payload(multipart(parts));
+                if let Some(bucket) = bucket_header {
+                    req = req.insert_header(("bucket", bucket.to_string()));
+                }
+                req.to_request()
+            };
+
+            let req = upload(
+                None,
+                &[
+                    ("bucket", None, "holiday2024"),
+                    ("file", Some("a.txt"), "a"),
+                    ("file", Some("b.txt"), "b"),
+                ],
+            );
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = test::read_body(resp).await;
+            assert_eq!(
+                std::str::from_utf8(&body).unwrap(),
+                format!("{0}/holiday2024/a.txt,{0}/holiday2024/b.txt", user)
+            );
+
+            let req = upload(Some("holiday2024"), &[("file", Some("c.txt"), "c")]);
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+            let req = upload(Some("tus"), &[("file", Some("c.txt"), "c")]);
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+            let req = upload(Some("no-dashes"), &[("file", Some("c.txt"), "c")]);
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+            let req = upload(
+                None,
+                &[("file", Some("c.txt"), "c"), ("bucket", None, "late")],
+            );
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+
+        #[actix_web::test]
+        async fn file_200_group_uploads() {
+            let config = Config {
+                group_uploads: true,
+                bucket_names: BucketNames {
+                    style: NameStyle::Words,
+                    words: vec!["brave".into(), "otter".into()],
+                    ..BucketNames::default()
+                },
+                ..memory_config()
+            };
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, upload_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((
+                    "content-type",
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                ))
+                .set_payload(multipart(&[
+                    ("file", Some("a.txt"), "a"),
+                    ("file", Some("b.txt"), "b"),
+                ]))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = test::read_body(resp).await;
+            let paths: Vec<Vec<&str>> = std::str::from_utf8(&body)
+                .unwrap()
+                .split(',')
+                .map(|path| path.split('/').collect())
+                .collect();
+            assert_eq!(paths.len(), 2);
+            assert_eq!(paths[0][1], paths[1][1]);
+            let words: Vec<&[u8]> = paths[0][1].as_bytes().chunks(5).collect();
+            assert_eq!(words.len(), 3);
+            assert!(words
+                .iter()
+                .all(|word| *word == b"Brave" || *word == b"Otter"));
+        }
+    }
+
+    mod test_is_authed_header {
+        use crate::backend::open_backend;
+        use crate::config::{ApiKey, ProxyHeader, Scope};
+        use crate::keys::hash_key;
+        use crate::{delete_file, sharex_uploader, Config, UserData};
+        use actix_web::http::{header, StatusCode};
+        use actix_web::{test, web, App};
+
+        fn config() -> Config {
+            let mut config = Config::default();
+            config.users.insert(
+                "user2".to_string(),
+                UserData {
+                    key: hash_key(&config.key_secret, "key2"),
+                    keys: vec![
+                        ApiKey {
+                            name: "ci".to_string(),
+                            key: "cikey".to_string(),
+                            scopes: vec![Scope::Upload],
+                            expires: None,
+                            allowed_ips: Vec::new(),
+                        },
+                        ApiKey {
+                            name: "old".to_string(),
+                            key: "oldkey".to_string(),
+                            scopes: vec![Scope::Upload],
+                            expires: Some(1),
+                            allowed_ips: Vec::new(),
+                        },
+                        ApiKey {
+                            name: "office".to_string(),
+                            key: "officekey".to_string(),
+                            scopes: vec![Scope::Upload],
+                            expires: None,
+                            allowed_ips: vec!["10.0.0.0/8".parse().unwrap()],
+                        },
+                    ],
+                    folder: "user2".to_string(),
+                    ..UserData::default()
+                },
+            );
+            config
+        }
+
+        async fn status_from(
+            config: &Config,
+            key: &str,
+            peer: &str,
+            forwarded: &str,
+        ) -> StatusCode {
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(config.clone()))
+                    .service(sharex_uploader),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header((header::AUTHORIZATION, format!("Bearer {}", key)))
+                .insert_header(("x-forwarded-for", forwarded))
+                .peer_addr(peer.parse().unwrap())
+                .to_request();
+
+            test::call_service(&app, req).await.status()
+        }
+
+        async fn username(authorization: &str) -> (StatusCode, Option<String>) {
+            let config = config();
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(config.clone()))
+                    .service(sharex_uploader),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header((header::AUTHORIZATION, authorization))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            let status = resp.status();
+            if status != StatusCode::OK {
+                return (status, None);
+            }
+            let uploader: serde_json::Value = test::read_body_json(resp).await;
+
+            (
+                status,
+                uploader["Headers"]["username"].as_str().map(str::to_string),
+            )
+        }
+
+        #[actix_web::test]
+        async fn bearer_200() {
+            assert_eq!(
+                username("Bearer key2").await,
+                (StatusCode::OK, Some("user2".to_string()))
+            );
+            assert_eq!(
+                username("bearer   key2").await,
+                (StatusCode::OK, Some("user2".to_string()))
+            );
+        }
+
+        #[actix_web::test]
+        async fn bearer_403() {
+            assert_eq!(username("Bearer key3").await, (StatusCode::FORBIDDEN, None));
+        }
+
+        #[actix_web::test]
+        async fn basic_200() {
+            // user2:key2
+            assert_eq!(
+                username("Basic dXNlcjI6a2V5Mg==").await,
+                (StatusCode::OK, Some("user2".to_string()))
+            );
+            // :key2
+            assert_eq!(
+                username("Basic OmtleTI=").await,
+                (StatusCode::OK, Some("user2".to_string()))
+            );
+        }
+
+        #[actix_web::test]
+        async fn basic_403() {
+            // default_user:key2
+            assert_eq!(
+                username("Basic ZGVmYXVsdF91c2VyOmtleTI=").await,
+                (StatusCode::FORBIDDEN, None)
+            );
+        }
+
+        #[actix_web::test]
+        async fn scope_403() {
+            assert_eq!(
+                username("Bearer cikey").await,
+                (StatusCode::OK, Some("user2".to_string()))
+            );
+
+            let config = Config { ..config() };
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, delete_file);
+            let req = test::TestRequest::delete()
+                .uri("/bucket/file.txt")
+                .insert_header((header::AUTHORIZATION, "Bearer cikey"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+
+        #[actix_web::test]
+        async fn expired_403() {
+            assert_eq!(
+                username("Bearer oldkey").await,
+                (StatusCode::FORBIDDEN, None)
+            );
+        }
+
+        #[actix_web::test]
+        async fn allowed_ips() {
+            let mut config = config();
+            assert_eq!(
+                status_from(&config, "officekey", "10.1.2.3:4000", "192.168.0.1").await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                status_from(&config, "officekey", "192.168.0.1:4000", "10.1.2.3").await,
+                StatusCode::FORBIDDEN
+            );
+            assert_eq!(
+                status_from(&config, "key2", "192.168.0.1:4000", "10.1.2.3").await,
+                StatusCode::OK
+            );
+
+            config.behind_proxy = true;
+            assert_eq!(
+                status_from(&config, "officekey", "192.168.0.1:4000", "10.1.2.3").await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                status_from(&config, "officekey", "10.1.2.3:4000", "192.168.0.1").await,
+                StatusCode::FORBIDDEN
+            );
+            // The first entry is sent by the client, the proxy appends the actual address.
+            assert_eq!(
+                status_from(
+                    &config,
+                    "officekey",
+                    "127.0.0.1:4000",
+                    "10.1.2.3, 192.168.0.1"
+                )
+                .await,
+                StatusCode::FORBIDDEN
+            );
+            assert_eq!(
+                status_from(
+                    &config,
+                    "officekey",
+                    "127.0.0.1:4000",
+                    "192.168.0.1, 10.1.2.3"
+                )
+                .await,
+                StatusCode::OK
+            );
+
+            config.trusted_proxies = vec!["192.168.0.0/16".parse().unwrap()];
+            assert_eq!(
+                status_from(
+                    &config,
+                    "officekey",
+                    "127.0.0.1:4000",
+                    "10.1.2.3, 192.168.0.1"
+                )
+                .await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                status_from(
+                    &config,
+                    "officekey",
+                    "127.0.0.1:4000",
+                    "10.1.2.3, 172.16.0.1, 192.168.0.1"
+                )
+                .await,
+                StatusCode::FORBIDDEN
+            );
+        }
+
+        #[actix_web::test]
+        async fn forwarded_addr_formats() {
+            use crate::parse_forwarded_addr;
+
+            for addr in ["10.1.2.3", "\"10.1.2.3:80\"", " 10.1.2.3 "] {
+                assert_eq!(
+                    parse_forwarded_addr(addr),
+                    Some("10.1.2.3".parse().unwrap())
+                );
+            }
+            for addr in ["\"[::1]:80\"", "[::1]", "::1"] {
+                assert_eq!(parse_forwarded_addr(addr), Some("::1".parse().unwrap()));
+            }
+            assert_eq!(parse_forwarded_addr("unknown"), None);
+        }
+
+        #[actix_web::test]
+        async fn forwarded_header() {
+            let mut config = config();
+            config.behind_proxy = true;
+            config.proxy_header = ProxyHeader::Forwarded;
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(config.clone()))
+                    .service(sharex_uploader),
+            )
+            .await;
+            let status = |peer: &'static str, forwarded: &'static str| {
+                test::TestRequest::get()
+                    .uri("/sharex.sxcu")
+                    .insert_header((header::AUTHORIZATION, "Bearer officekey"))
+                    .insert_header((header::FORWARDED, forwarded))
+                    // Passed on by the proxy, only `Forwarded` is set by it.
+                    .insert_header(("x-forwarded-for", "10.1.2.3"))
+                    .peer_addr(peer.parse().unwrap())
+                    .to_request()
+            };
+
+            let resp = test::call_service(
+                &app,
+                status(
+                    "127.0.0.1:4000",
+                    "for=192.168.0.1, for=10.1.2.3;proto=https",
+                ),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let resp = test::call_service(
+                &app,
+                status("127.0.0.1:4000", "for=10.1.2.3, for=\"192.168.0.1:80\""),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            // Unknown hops fall back to the peer, not to the client supplied addresses before them.
+            let resp =
+                test::call_service(&app, status("127.0.0.1:4000", "for=10.1.2.3, for=unknown"))
+                    .await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            let resp = test::call_service(
+                &app,
+                status("10.0.0.1:4000", "for=192.168.0.1, for=unknown"),
+            )
+            .await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        #[actix_web::test]
+        async fn x_forwarded_for_ignores_forwarded() {
+            let mut config = config();
+            config.behind_proxy = true;
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(config.clone()))
+                    .service(sharex_uploader),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header((header::AUTHORIZATION, "Bearer officekey"))
+                .insert_header((header::FORWARDED, "for=10.1.2.3"))
+                .insert_header(("x-forwarded-for", "192.168.0.1"))
+                .peer_addr("127.0.0.1:4000".parse().unwrap())
+                .to_request();
+
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+
+        #[actix_web::test]
+        async fn malformed_400() {
+            assert_eq!(username("Bearer").await, (StatusCode::BAD_REQUEST, None));
+            assert_eq!(username("Basic !!!").await, (StatusCode::BAD_REQUEST, None));
+            // no colon
+            assert_eq!(
+                username("Basic dXNlcjI=").await,
+                (StatusCode::BAD_REQUEST, None)
+            );
+        }
+
+        #[actix_web::test]
+        async fn scheme_401() {
+            let config = config();
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(config.clone()))
+                    .service(sharex_uploader),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header((header::AUTHORIZATION, "Digest username=\"user2\""))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+            let challenges: Vec<_> = resp
+                .headers()
+                .get_all(header::WWW_AUTHENTICATE)
+                .map(|value| value.to_str().unwrap())
+                .collect();
+            assert_eq!(
+                challenges,
+                vec!["Bearer realm=\"share.rs\"", "Basic realm=\"share.rs\""]
+            );
+        }
+
+        #[actix_web::test]
+        async fn non_ascii_400() {
+            let config = config();
+            let app = test::init_service(
+                App::new()
+                    .app_data(web::Data::new(config.clone()))
+                    .service(sharex_uploader),
+            )
+            .await;
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header(("username", "user2"))
+                .insert_header((
+                    "auth",
+                    header::HeaderValue::from_bytes(b"k\xffey2").unwrap(),
+                ))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(
+                resp.headers().get(header::WWW_AUTHENTICATE).unwrap(),
+                "Bearer realm=\"share.rs\", error=\"invalid_request\""
+            );
+        }
+    }
+
+    mod test_find_thumbnail {
+        use crate::backend::open_backend;
+        use crate::config::BackendKind;
+        use crate::tests::{memory_config, write_test_file};
+        use crate::{find_thumbnail, Bucket, Config, FileMeta, StorageFile, UserDir};
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test;
+        use actix_web::web::Bytes;
+        use image::{GenericImageView, ImageFormat, Rgb, RgbImage};
+        use std::io::Cursor;
+
+        #[actix_web::test]
+        async fn thumbnail_200() {
+            let config = Config {
+                backend: BackendKind::Memory,
+                thumbnail_width: 100,
+                thumbnail_height: 100,
+                ..memory_config()
+            };
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, find_thumbnail);
+
+            let mut png = Vec::new();
+            RgbImage::from_pixel(400, 200, Rgb([200, 100, 50]))
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .unwrap();
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, None).unwrap();
+            let storage_file = StorageFile::new(&bucket, "image.png".into());
+            storage_file
+                .write(
+                    futures::stream::iter([Ok::<_, actix_web::Error>(Bytes::from(png))]),
+                    FileMeta::default(),
+                )
+                .await
+                .unwrap();
+
+            for _ in 0..2 {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/{}/{}/image.png/thumb", user, &bucket.name))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), StatusCode::OK);
+                assert_eq!(
+                    resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                    "image/jpeg"
+                );
+                let body = test::read_body(resp).await;
+                let thumbnail = image::load_from_memory(&body).unwrap();
+                assert_eq!(thumbnail.dimensions(), (100, 50));
+                assert!(storage_file.thumbnail().await.is_some());
+            }
+
+            storage_file.delete().await.unwrap();
+            assert!(bucket.open(false).await.is_none());
+        }
+
+        #[actix_web::test]
+        async fn thumbnail_404() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, find_thumbnail);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, None).unwrap();
+            for filename in ["notes.txt", "broken.png"] {
+                write_test_file(&StorageFile::new(&bucket, filename.into())).await;
+            }
+
+            for (filename, status) in [
+                ("notes.txt", StatusCode::NOT_FOUND),
+                ("missing.png", StatusCode::NOT_FOUND),
+                ("broken.png", StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            ] {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/{}/{}/{}/thumb", user, &bucket.name, filename))
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), status, "{}", filename);
+            }
+        }
+    }
+
+    mod test_paste_text {
+        use crate::backend::open_backend;
+        use crate::tests::memory_config;
+        use crate::{paste_text, view_file, Bucket, StorageFile, UserDir};
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test;
+
+        #[actix_web::test]
+        async fn paste_200() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, paste_text, view_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/paste?language=rust&format=json")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload("fn main() {}\n")
+                .to_request();
+            let upload: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            let url = upload["url"].as_str().unwrap();
+            assert!(url.ends_with("/paste.rs"));
+            assert_eq!(upload["view_url"], format!("{}/view", url));
+            assert_eq!(upload["size"], 13);
+
+            let bucket_name = url.rsplit('/').nth(1).unwrap().to_string();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some(bucket_name)).unwrap();
+            let meta = StorageFile::new(&bucket, "paste.rs".into()).meta().await;
+            assert_eq!(meta.language.as_deref(), Some("rust"));
+            assert_eq!(meta.uploader.as_deref(), Some(user.as_str()));
+
+            let req = test::TestRequest::get()
+                .uri(&format!("/{}/{}/paste.rs/view", user, &bucket.name))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = test::read_body(resp).await;
+            let html = std::str::from_utf8(&body).unwrap();
+            assert!(html.contains("<span>Rust</span>"));
+            assert!(html.contains("id=\"L1\""));
+
+            let req = test::TestRequest::post()
+                .uri("/paste")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+                .set_payload("text=print%28%27hi%27%29&language=python")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            let body = test::read_body(resp).await;
+            let path = std::str::from_utf8(&body).unwrap();
+            assert!(path.ends_with("/paste.py/view"));
+
+            let req = test::TestRequest::get()
+                .uri(&format!("/{}", path))
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            assert!(std::str::from_utf8(&body).unwrap().contains("Python"));
+        }
+
+        #[actix_web::test]
+        async fn paste_400() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, paste_text);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/paste?language=klingon")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload("Qapla'")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+            let req = test::TestRequest::post()
+                .uri("/paste")
+                .set_payload("Qapla'")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        }
+
+        #[actix_web::test]
+        async fn view_415() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, paste_text, view_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/paste")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload(&b"\xff\xfe"[..])
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let path = std::str::from_utf8(&body).unwrap();
+
+            let req = test::TestRequest::get()
+                .uri(&format!("/{}", path))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
 
             let req = test::TestRequest::get()
-                .uri(&*format!(
-                    "/{}/{}/{}",
-                    user, &bucket.name, &storage_file.name
-                ))
+                .uri(&format!("/{}/Missing/paste.txt/view", user))
                 .to_request();
             let resp = test::call_service(&app, req).await;
-
-            storage_file.delete().await.unwrap();
-
-            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
         }
     }
 
-    mod test_get_delete_file {
-        use crate::{get_delete_file, Bucket, Config, StorageFile, UserDir};
-        use actix_web::http::StatusCode;
-        use actix_web::{test, web, App};
-        use std::io::Write;
+    mod test_shorten_url {
+        use crate::backend::open_backend;
+        use crate::shortlink;
+        use crate::tests::memory_config;
+        use crate::{
+            delete_file, find_file, follow_short_link, shorten_url, Bucket, StorageFile, UserDir,
+            LINK_FILE,
+        };
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test;
 
         #[actix_web::test]
-        async fn file_200() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(get_delete_file),
-            )
-            .await;
+        async fn shorten_200() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(
+                config,
+                backend,
+                shorten_url,
+                follow_short_link,
+                delete_file,
+                find_file
+            );
 
-            let filename = "file.txt";
-            let (user, user_data) = *(&config.users).iter().peekable().peek().unwrap();
-            let user_dir = UserDir::new(&config, user_data);
-            let bucket = Bucket::new(&user_dir, None).unwrap();
-            let storage_file = StorageFile::new(&bucket, filename.into());
-            {
-                let mut file = storage_file.open(true).await.unwrap();
-                file = web::block(move || file.write_all(b"This is a testfile!").map(|_| file))
-                    .await
-                    .unwrap()
-                    .unwrap();
-                web::block(move || file.flush()).await.unwrap().unwrap();
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/shorten?format=json")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((header::CONTENT_TYPE, "application/x-www-form-urlencoded"))
+                .set_payload("url=https%3A%2F%2Fexample.com%2F%3Fq%3D1")
+                .to_request();
+            let link: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(link["target"], "https://example.com/?q=1");
+            let url = link["url"].as_str().unwrap();
+            let path = url.strip_prefix(&config.public_url()).unwrap();
+            let id = path.strip_prefix("/s/").unwrap();
+            assert_eq!(id.len(), 6);
+
+            for _ in 0..2 {
+                let req = test::TestRequest::get().uri(path).to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), StatusCode::FOUND);
+                assert_eq!(
+                    resp.headers().get(header::LOCATION).unwrap(),
+                    "https://example.com/?q=1"
+                );
             }
 
-            let req = test::TestRequest::get()
-                .uri(&*format!(
-                    "/delete/{}/{}?username={}&auth={}",
-                    &bucket.name, &storage_file.name, user, &user_data.key
-                ))
+            let deletion_url = link["deletion_url"].as_str().unwrap();
+            let bucket_name = deletion_url.split('/').rev().nth(1).unwrap().to_string();
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            let bucket = Bucket::new(&user_dir, Some(bucket_name)).unwrap();
+            let storage_file = StorageFile::new(&bucket, LINK_FILE.into());
+            assert_eq!(storage_file.clicks().await, 2);
+
+            let req = test::TestRequest::delete()
+                .uri(&format!("/{}/{}", &bucket.name, LINK_FILE))
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
                 .to_request();
             let resp = test::call_service(&app, req).await;
-
-            assert!(storage_file.open_path(false).await.is_none());
             assert_eq!(resp.status(), StatusCode::OK);
+            assert!(bucket.open(false).await.is_none());
+            assert!(shortlink::resolve(backend.as_ref(), id).await.is_none());
+
+            let req = test::TestRequest::get().uri(path).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
         }
 
         #[actix_web::test]
-        async fn file_404() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(get_delete_file),
-            )
-            .await;
+        async fn shorten_expired_404() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, shorten_url, follow_short_link);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/shorten?expires=0")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload("https://example.com/")
+                .to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            let path = std::str::from_utf8(&body).unwrap();
+            assert!(path.starts_with("s/"));
 
-            let (user, user_data) = *(&config.users).iter().peekable().peek().unwrap();
             let req = test::TestRequest::get()
-                .uri(&*format!(
-                    "/delete/bucket/file.txt?username={}&auth={}",
-                    user, &user_data.key
-                ))
+                .uri(&format!("/{}", path))
                 .to_request();
             let resp = test::call_service(&app, req).await;
-
             assert_eq!(resp.status(), StatusCode::NOT_FOUND);
         }
 
         #[actix_web::test]
-        async fn file_403_auth() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(get_delete_file),
-            )
-            .await;
+        async fn shorten_400() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, shorten_url);
 
-            let (user, _) = *(&config.users).iter().peekable().peek().unwrap();
-            let req = test::TestRequest::get()
-                .uri(&*format!(
-                    "/delete/bucket/file.txt?username={}&auth=456",
-                    user
-                ))
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            for url in ["", "javascript:alert(1)", "ftp://example.com/", "not a url"] {
+                let req = test::TestRequest::post()
+                    .uri("/shorten")
+                    .insert_header(("username", user.clone()))
+                    .insert_header(("auth", user_data.key.clone()))
+                    .set_payload(url)
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", url);
+            }
+
+            let req = test::TestRequest::post()
+                .uri("/shorten")
+                .set_payload("https://example.com/")
                 .to_request();
             let resp = test::call_service(&app, req).await;
-
             assert_eq!(resp.status(), StatusCode::FORBIDDEN);
         }
+    }
+
+    mod test_put_file {
+        use crate::backend::open_backend;
+        use crate::tests::memory_config;
+        use crate::{find_file, put_file, put_file_in_bucket, Config, MAX_STRIP_SIZE};
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test;
 
         #[actix_web::test]
-        async fn file_403_user() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(get_delete_file),
-            )
-            .await;
+        async fn put_200() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, put_file, put_file_in_bucket, find_file);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::put()
+                .uri("/notes%20v2")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header((header::CONTENT_TYPE, "text/markdown"))
+                .set_payload("# Notes")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert!(resp.headers().contains_key("Deletion-Url"));
+            let path = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+            assert!(path.starts_with(&format!("{}/", user)));
+            assert!(path.ends_with("/notes%20v2.markdown"));
 
-            let (_, user_data) = *(&config.users).iter().peekable().peek().unwrap();
             let req = test::TestRequest::get()
-                .uri(&*format!(
-                    "/delete/bucket/file.txt?username=someone&auth={}",
-                    &user_data.key
-                ))
+                .uri(&format!("/{}", path))
                 .to_request();
             let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/markdown"
+            );
+            assert_eq!(test::read_body(resp).await, "# Notes");
 
-            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            let req = test::TestRequest::put()
+                .uri("/mybucket/file.bin?format=json")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload("content")
+                .to_request();
+            let upload: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(
+                upload["url"],
+                format!("{}/{}/mybucket/file.bin", config.public_url(), user)
+            );
+            assert_eq!(upload["size"], 7);
+
+            let req = test::TestRequest::put()
+                .uri("/mybucket/file.bin")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload("other")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+            let req = test::TestRequest::put()
+                .uri("/mybucket/other.bin")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload("other")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+
+            for bucket in ["my-bucket", "tus"] {
+                let req = test::TestRequest::put()
+                    .uri(&format!("/{}/file.bin", bucket))
+                    .insert_header(("username", user.clone()))
+                    .insert_header(("auth", user_data.key.clone()))
+                    .set_payload("other")
+                    .to_request();
+                let resp = test::call_service(&app, req).await;
+                assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", bucket);
+            }
         }
 
         #[actix_web::test]
-        async fn file_400_user() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(get_delete_file),
-            )
-            .await;
+        async fn put_200_strip_metadata_too_large() {
+            let config = Config {
+                strip_metadata: true,
+                ..memory_config()
+            };
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, put_file);
 
-            let req = test::TestRequest::get()
-                .uri("/delete/bucket/file.txt")
+            let mut webp = b"RIFF\0\0\0\0WEBPEXIF\x05\0\0\0GPS!!\0".to_vec();
+            webp.resize(MAX_STRIP_SIZE as usize + 1, 0);
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::put()
+                .uri("/photo.webp?format=json")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload(webp)
+                .to_request();
+            let upload: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(upload["metadata_stripped"], false);
+            assert_eq!(upload["size"], MAX_STRIP_SIZE + 1);
+        }
+
+        #[actix_web::test]
+        async fn put_403_413() {
+            let config = Config {
+                max_file_size: Some(4),
+                ..memory_config()
+            };
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, put_file);
+
+            let req = test::TestRequest::put()
+                .uri("/file.txt")
+                .set_payload("data")
                 .to_request();
             let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
-            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::put()
+                .uri("/file.txt")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .set_payload("too large")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
         }
     }
 
-    mod test_delete_file {
-        use crate::{delete_file, Bucket, Config, StorageFile, UserDir};
-        use actix_web::http::StatusCode;
-        use actix_web::{test, web, App};
-        use std::io::Write;
+    mod test_tus {
+        use crate::backend::open_backend;
+        use crate::tests::memory_config;
+        use crate::{
+            delete_file, find_file, tus_append, tus_create, tus_offset, tus_options, tus_terminate,
+            Bucket, Config, TusInfo, TusUpload, UserDir,
+        };
+        use actix_web::dev::ServiceResponse;
+        use actix_web::http::{header, StatusCode};
+        use actix_web::test;
+
+        fn create(config: &Config, length: u64) -> test::TestRequest {
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            test::TestRequest::post()
+                .uri("/tus")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header(("Tus-Resumable", "1.0.0"))
+                .insert_header(("Upload-Length", length.to_string()))
+                .insert_header((
+                    "Upload-Metadata",
+                    "filename aGVsbG8udHh0,filetype dGV4dC9wbGFpbg==",
+                ))
+        }
+
+        /// The path of the upload a `create` request made.
+        fn location(config: &Config, resp: &ServiceResponse) -> String {
+            assert_eq!(resp.status(), StatusCode::CREATED);
+            assert!(resp.headers().contains_key("Upload-Expires"));
+            let location = resp
+                .headers()
+                .get(header::LOCATION)
+                .unwrap()
+                .to_str()
+                .unwrap();
+            location
+                .strip_prefix(&config.public_url())
+                .unwrap()
+                .to_string()
+        }
+
+        fn patch(
+            config: &Config,
+            path: &str,
+            offset: u64,
+            body: &'static str,
+        ) -> test::TestRequest {
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            test::TestRequest::patch()
+                .uri(path)
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header(("Tus-Resumable", "1.0.0"))
+                .insert_header(("Upload-Offset", offset.to_string()))
+                .insert_header((header::CONTENT_TYPE, "application/offset+octet-stream"))
+                .set_payload(body)
+        }
 
         #[actix_web::test]
-        async fn file_200() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(delete_file),
-            )
-            .await;
+        async fn tus_200() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(
+                config,
+                backend,
+                tus_options,
+                tus_create,
+                tus_offset,
+                tus_append,
+                tus_terminate,
+                delete_file,
+                find_file
+            );
 
-            let filename = "file.txt";
-            let (user, user_data) = *(&config.users).iter().peekable().peek().unwrap();
-            let user_dir = UserDir::new(&config, user_data);
-            let bucket = Bucket::new(&user_dir, None).unwrap();
-            let storage_file = StorageFile::new(&bucket, filename.into());
-            {
-                let mut file = storage_file.open(true).await.unwrap();
-                file = web::block(move || file.write_all(b"This is a testfile!").map(|_| file))
-                    .await
-                    .unwrap()
-                    .unwrap();
-                web::block(move || file.flush()).await.unwrap().unwrap();
-            }
+            let req = test::TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/tus")
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            assert_eq!(resp.headers().get("Tus-Version").unwrap(), "1.0.0");
+            assert!(resp.headers().contains_key("Tus-Extension"));
+
+            let resp = test::call_service(&app, create(&config, 11).to_request()).await;
+            let path = location(&config, &resp);
+            let resp =
+                test::call_service(&app, patch(&config, &path, 0, "hello ").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "6");
+            assert!(!resp.headers().contains_key("File-Url"));
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::default()
+                .method(actix_web::http::Method::HEAD)
+                .uri(&path)
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header(("Tus-Resumable", "1.0.0"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "6");
+            assert_eq!(resp.headers().get("Upload-Length").unwrap(), "11");
+            assert_eq!(
+                resp.headers().get(header::CACHE_CONTROL).unwrap(),
+                "no-store"
+            );
+
+            let resp =
+                test::call_service(&app, patch(&config, &path, 0, "hello ").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+            let resp =
+                test::call_service(&app, patch(&config, &path, 6, "world").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            assert_eq!(resp.headers().get("Upload-Offset").unwrap(), "11");
+            let url = resp.headers().get("File-Url").unwrap().to_str().unwrap();
+            assert!(url.ends_with("/hello.txt"));
+            let file_path = url.strip_prefix(&config.public_url()).unwrap().to_string();
+
+            let req = test::TestRequest::get().uri(&file_path).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                "text/plain"
+            );
+            assert_eq!(test::read_body(resp).await, "hello world");
+
+            // Asking again after the upload completed still yields the file.
+            let resp = test::call_service(&app, patch(&config, &path, 11, "").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            assert_eq!(
+                resp.headers().get("File-Url").unwrap().to_str().unwrap(),
+                url
+            );
 
             let req = test::TestRequest::delete()
-                .uri(&*format!("/{}/{}", &bucket.name, &storage_file.name))
+                .uri(&path)
                 .insert_header(("username", user.clone()))
                 .insert_header(("auth", user_data.key.clone()))
+                .insert_header(("Tus-Resumable", "1.0.0"))
                 .to_request();
             let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            let resp = test::call_service(&app, patch(&config, &path, 11, "").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND);
 
-            assert!(storage_file.open_path(false).await.is_none());
+            let req = test::TestRequest::get().uri(&file_path).to_request();
+            let resp = test::call_service(&app, req).await;
             assert_eq!(resp.status(), StatusCode::OK);
         }
 
         #[actix_web::test]
-        async fn file_404() {
-            let config = Config::default();
-            let app = test::init_service(
-                App::new()
-                    .app_data(web::Data::new(config.clone()))
-                    .service(delete_file),
-            )
-            .await;
+        async fn tus_retry_finishes_into_the_same_file() {
+            let config = memory_config();
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, tus_create, tus_append);
 
-            let (user, user_data) = *(&config.users).iter().peekable().peek().unwrap();
-            let req = test::TestRequest::delete()
-                .uri("/bucket/file.txt")
+            let resp = test::call_service(&app, create(&config, 11).to_request()).await;
+            let path = location(&config, &resp);
+            let resp =
+                test::call_service(&app, patch(&config, &path, 0, "hello world").to_request())
+                    .await;
+            let url = resp.headers().get("File-Url").unwrap().to_str().unwrap();
+            let (_, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let bucket_name = url.rsplit('/').nth(1).unwrap().to_string();
+
+            // As if recording the url failed after the file was written.
+            let upload =
+                TusUpload::new(backend.as_ref(), path.rsplit('/').next().map(String::from))
+                    .unwrap();
+            let info = upload.info().await.unwrap();
+            assert_eq!(info.bucket.as_ref(), Some(&bucket_name));
+            upload
+                .write_info(&TusInfo {
+                    url: None,
+                    deletion_url: None,
+                    ..info
+                })
+                .await
+                .unwrap();
+
+            let resp = test::call_service(&app, patch(&config, &path, 11, "").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            assert_eq!(
+                resp.headers().get("File-Url").unwrap().to_str().unwrap(),
+                url
+            );
+            let user_dir = UserDir::new(backend.as_ref(), user_data);
+            assert_eq!(user_dir.buckets().await.unwrap().len(), 1);
+            let bucket = Bucket::new(&user_dir, Some(bucket_name)).unwrap();
+            assert_eq!(bucket.files().await.unwrap(), vec!["hello.txt".to_string()]);
+            assert_eq!(user_dir.usage().await.unwrap(), 11);
+        }
+
+        #[actix_web::test]
+        async fn tus_errors() {
+            let config = Config {
+                max_file_size: Some(100),
+                ..memory_config()
+            };
+            let backend = open_backend(&config);
+            let app = init_app!(config, backend, tus_create, tus_append);
+
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::post()
+                .uri("/tus")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header(("Upload-Length", "10"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+            assert_eq!(resp.headers().get("Tus-Resumable").unwrap(), "1.0.0");
+            assert_eq!(resp.headers().get("Tus-Version").unwrap(), "1.0.0");
+
+            let req = test::TestRequest::post()
+                .uri("/tus")
                 .insert_header(("username", user.clone()))
                 .insert_header(("auth", user_data.key.clone()))
+                .insert_header(("Tus-Resumable", "1.0.0"))
+                .insert_header(("Upload-Length", "101"))
                 .to_request();
             let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert_eq!(resp.headers().get("Tus-Resumable").unwrap(), "1.0.0");
 
+            let req = test::TestRequest::post()
+                .uri("/tus")
+                .insert_header(("Tus-Resumable", "1.0.0"))
+                .insert_header(("Upload-Length", "10"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            assert_eq!(resp.headers().get("Tus-Resumable").unwrap(), "1.0.0");
+
+            let resp = test::call_service(&app, create(&config, 4).to_request()).await;
+            let path = location(&config, &resp);
+            let req = patch(&config, &path, 0, "data")
+                .insert_header((header::CONTENT_TYPE, "text/plain"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+            assert_eq!(resp.headers().get("Tus-Resumable").unwrap(), "1.0.0");
+
+            let resp =
+                test::call_service(&app, patch(&config, &path, 0, "too long").to_request()).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(resp.headers().get("Tus-Resumable").unwrap(), "1.0.0");
+
+            let resp =
+                test::call_service(&app, patch(&config, "/tus/unknown", 0, "data").to_request())
+                    .await;
             assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+            assert_eq!(resp.headers().get("Tus-Resumable").unwrap(), "1.0.0");
+
+            let req = create(&config, 4)
+                .insert_header(("Upload-Metadata", "filename Li4u"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+            assert_eq!(resp.headers().get("Tus-Resumable").unwrap(), "1.0.0");
         }
 
         #[actix_web::test]
-        async fn file_403() {
+        async fn tus_quota() {
+            let mut config = Config {
+                quota_bytes: Some(20),
+                ..memory_config()
+            };
+            config.users.values_mut().next().unwrap().max_file_size = Some(15);
+            let backend = open_backend(&config);
+            let app = init_app!(
+                config,
+                backend,
+                tus_options,
+                tus_create,
+                tus_append,
+                tus_terminate
+            );
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+
+            let options = test::TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/tus");
+            let resp = test::call_service(&app, options.to_request()).await;
+            assert!(!resp.headers().contains_key("Tus-Max-Size"));
+            let options = test::TestRequest::default()
+                .method(actix_web::http::Method::OPTIONS)
+                .uri("/tus")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()));
+            let resp = test::call_service(&app, options.to_request()).await;
+            assert_eq!(resp.headers().get("Tus-Max-Size").unwrap(), "15");
+
+            let resp = test::call_service(&app, create(&config, 16).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+            // The length of unfinished uploads is reserved, together they may not exceed the quota.
+            let resp = test::call_service(&app, create(&config, 12).to_request()).await;
+            let path = location(&config, &resp);
+            let resp = test::call_service(&app, create(&config, 12).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+            let req = test::TestRequest::delete()
+                .uri(&path)
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
+                .insert_header(("Tus-Resumable", "1.0.0"))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+            let resp = test::call_service(&app, create(&config, 12).to_request()).await;
+            let path = location(&config, &resp);
+            let resp =
+                test::call_service(&app, patch(&config, &path, 0, "hello world!").to_request())
+                    .await;
+            assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+            assert!(resp.headers().contains_key("File-Url"));
+            assert_eq!(resp.headers().get("Metadata-Stripped").unwrap(), "false");
+
+            let resp = test::call_service(&app, create(&config, 9).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            let resp = test::call_service(&app, create(&config, 8).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+
+            // After a restart the unfinished upload is still counted.
+            let mut config = config.clone();
+            config.users.values_mut().next().unwrap().usage = Default::default();
+            let app = init_app!(config, backend, tus_create);
+            let resp = test::call_service(&app, create(&config, 1).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    }
+
+    mod test_sharex_uploader {
+        use crate::keys::hash_key;
+        use crate::{sharex_uploader, Config};
+        use actix_web::http::StatusCode;
+        use actix_web::{test, web, App};
+
+        #[actix_web::test]
+        async fn uploader_200() {
             let config = Config::default();
             let app = test::init_service(
                 App::new()
                     .app_data(web::Data::new(config.clone()))
-                    .service(delete_file),
+                    .service(sharex_uploader),
             )
             .await;
 
-            let req = test::TestRequest::delete()
-                .uri("/bucket/file.txt")
+            let (user, user_data) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", user_data.key.clone()))
                 .to_request();
             let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert_eq!(
+                resp.headers().get("content-disposition").unwrap(),
+                "attachment; filename=\"localhost_8080 (default_user).sxcu\""
+            );
 
-            assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+            let uploader: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(uploader["RequestURL"], "http://localhost:8080/");
+            assert_eq!(uploader["Headers"]["username"], user.as_str());
+            assert_eq!(uploader["Headers"]["auth"], user_data.key.as_str());
         }
 
         #[actix_web::test]
-        async fn file_403_user() {
-            let config = Config::default();
+        async fn uploader_200_hashed_key() {
+            let mut config = Config::default();
+            config.users.get_mut("default_user").unwrap().key =
+                hash_key(&config.key_secret, "mysecret");
             let app = test::init_service(
                 App::new()
                     .app_data(web::Data::new(config.clone()))
-                    .service(delete_file),
+                    .service(sharex_uploader),
             )
             .await;
 
-            let (user, _) = *(&config.users).iter().peekable().peek().unwrap();
-            let req = test::TestRequest::delete()
-                .uri("/bucket/file.txt")
-                .insert_header(("username", user.clone()))
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header(("username", "default_user"))
+                .insert_header(("auth", "mysecret"))
                 .to_request();
             let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
 
+            let uploader: serde_json::Value = test::read_body_json(resp).await;
+            assert_eq!(uploader["Headers"]["auth"], "mysecret");
+
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header(("username", "default_user"))
+                .insert_header(("auth", config.users["default_user"].key.clone()))
+                .to_request();
+            let resp = test::call_service(&app, req).await;
             assert_eq!(resp.status(), StatusCode::FORBIDDEN);
         }
 
         #[actix_web::test]
-        async fn file_403_auth() {
+        async fn uploader_403() {
             let config = Config::default();
             let app = test::init_service(
                 App::new()
                     .app_data(web::Data::new(config.clone()))
-                    .service(delete_file),
+                    .service(sharex_uploader),
             )
             .await;
 
-            let (_, user_data) = *(&config.users).iter().peekable().peek().unwrap();
-            let req = test::TestRequest::delete()
-                .uri("/bucket/file.txt")
-                .insert_header(("auth", user_data.key.clone()))
+            let (user, _) = *config.users.iter().peekable().peek().unwrap();
+            let req = test::TestRequest::get()
+                .uri("/sharex.sxcu")
+                .insert_header(("username", user.clone()))
+                .insert_header(("auth", "456"))
                 .to_request();
             let resp = test::call_service(&app, req).await;
-
             assert_eq!(resp.status(), StatusCode::FORBIDDEN);
         }
     }
+
+    #[test]
+    fn test_parse_expires() {
+        use crate::parse_expires;
+
+        assert_eq!(parse_expires("30").unwrap(), Some(30));
+        assert_eq!(parse_expires("5m").unwrap(), Some(300));
+        assert_eq!(parse_expires("2d").unwrap(), Some(172800));
+        assert_eq!(parse_expires("never").unwrap(), None);
+        assert!(parse_expires("1y").is_err());
+        assert!(parse_expires("h").is_err());
+    }
 }
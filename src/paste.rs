@@ -0,0 +1,193 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+/// Larger texts are shown without highlighting, which would take too long for them.
+const MAX_HIGHLIGHT_SIZE: usize = 512 * 1024;
+/// Texts with more lines are shown without highlighting too, it takes time for every line.
+const MAX_HIGHLIGHT_LINES: usize = 10_000;
+const THEME: &str = "InspiredGitHub";
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    static THEME_: OnceLock<Theme> = OnceLock::new();
+    THEME_.get_or_init(|| {
+        ThemeSet::load_defaults()
+            .themes
+            .remove(THEME)
+            .expect("The theme is bundled with syntect.")
+    })
+}
+
+/// Escapes `text` for HTML, in text and quoted attributes.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for char in text.chars() {
+        match char {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            char => escaped.push(char),
+        }
+    }
+    escaped
+}
+
+/// The extension pastes in `language` are stored with, `language` is a name like `Rust` or an extension like `rs`.
+pub fn extension(language: &str) -> Option<&'static str> {
+    syntaxes()
+        .find_syntax_by_token(language)
+        .and_then(|syntax| syntax.file_extensions.first())
+        .map(String::as_str)
+}
+
+/// Whether the file can be shown in the viewer, judged by its extension.
+pub fn is_viewable(filename: &str, content_type: &mime::Mime) -> bool {
+    content_type.type_() == mime::TEXT
+        || Path::new(filename)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| syntaxes().find_syntax_by_extension(ext))
+            .is_some()
+}
+
+/// The syntax to highlight with, from the `language` if given, otherwise guessed from the filename or the first line.
+fn syntax(language: Option<&str>, filename: &str, text: &str) -> &'static SyntaxReference {
+    let syntaxes = syntaxes();
+    language
+        .and_then(|language| syntaxes.find_syntax_by_token(language))
+        .or_else(|| {
+            Path::new(filename)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .and_then(|ext| syntaxes.find_syntax_by_extension(ext))
+        })
+        .or_else(|| syntaxes.find_syntax_by_first_line(text))
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text())
+}
+
+/// The line as HTML, highlighted if a highlighter is given.
+fn render_line(highlighter: Option<&mut HighlightLines>, line: &str) -> String {
+    if let Some(highlighter) = highlighter {
+        if let Ok(regions) = highlighter.highlight_line(line, syntaxes()) {
+            let regions: Vec<_> = regions
+                .into_iter()
+                .map(|(style, text)| (style, text.trim_end_matches(['\r', '\n'])))
+                .collect();
+            if let Ok(html) = styled_line_to_highlighted_html(&regions, IncludeBackground::No) {
+                return html;
+            }
+        }
+    }
+
+    escape(line.trim_end_matches(['\r', '\n']))
+}
+
+/// Renders `text` as an HTML page with line numbers, every line can be linked to with `#L<number>`.
+pub fn render(filename: &str, raw_url: &str, text: &str, language: Option<&str>) -> String {
+    let syntax = syntax(language, filename, text);
+    let mut highlighter = (text.len() <= MAX_HIGHLIGHT_SIZE
+        && text.lines().count() <= MAX_HIGHLIGHT_LINES)
+        .then(|| HighlightLines::new(syntax, theme()));
+
+    let mut lines = String::new();
+    for (number, line) in LinesWithEndings::from(text).enumerate() {
+        let number = number + 1;
+        let _ = write!(
+            lines,
+            "<tr id=\"L{number}\"><td class=\"number\"><a href=\"#L{number}\">{number}</a></td><td class=\"line\">{}</td></tr>",
+            render_line(highlighter.as_mut(), line)
+        );
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <meta name="viewport" content="width=device-width, initial-scale=1">
+  <title>{filename} · share.rs</title>
+  <style>
+    body {{ margin: 0; font-family: system-ui, sans-serif; color: #18181b; }}
+    header {{ display: flex; gap: 1rem; align-items: center; padding: .75rem 1rem; background: #18181b; color: #fafafa; }}
+    header h1 {{ margin: 0 auto 0 0; font-size: 1.1rem; }}
+    header a {{ color: #fafafa; }}
+    table {{ border-collapse: collapse; font-family: ui-monospace, monospace; font-size: .85rem; }}
+    td {{ padding: 0 .75rem; vertical-align: top; }}
+    td.number {{ text-align: right; user-select: none; }}
+    td.number a {{ color: #a1a1aa; text-decoration: none; }}
+    td.line {{ white-space: pre; }}
+    tr:target {{ background: #fef9c3; }}
+  </style>
+</head>
+<body>
+<header>
+  <h1>{filename}</h1>
+  <span>{language}</span>
+  <a href="{raw_url}">Raw</a>
+  <a href="{raw_url}" download="{filename}">Download</a>
+</header>
+<table><tbody>{lines}</tbody></table>
+</body>
+</html>
+"#,
+        filename = escape(filename),
+        language = escape(&syntax.name),
+        raw_url = escape(raw_url),
+        lines = lines,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paste::{extension, is_viewable, render, MAX_HIGHLIGHT_LINES};
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension("rust"), Some("rs"));
+        assert_eq!(extension("Rust"), Some("rs"));
+        assert_eq!(extension("py"), Some("py"));
+        assert_eq!(extension("no such language"), None);
+
+        assert!(is_viewable("paste.rs", &mime::APPLICATION_OCTET_STREAM));
+        assert!(is_viewable("notes", &mime::TEXT_PLAIN));
+        assert!(!is_viewable("image.png", &mime::IMAGE_PNG));
+    }
+
+    #[test]
+    fn test_render() {
+        let html = render(
+            "paste.rs",
+            "/user1/bucket/paste.rs",
+            "fn main() {\n    println!(\"<b>\");\n}\n",
+            None,
+        );
+        assert!(html.contains("<span>Rust</span>"));
+        assert!(html.contains(r##"<tr id="L2"><td class="number"><a href="#L2">2</a>"##));
+        assert!(html.contains("&lt;b&gt;"));
+        assert!(!html.contains("<b>"));
+        assert!(!html.contains("id=\"L4\""));
+        assert!(html.contains(r#"<a href="/user1/bucket/paste.rs">Raw</a>"#));
+        assert!(html.contains("<span style="));
+
+        let text = "let x = 1;\n".repeat(MAX_HIGHLIGHT_LINES + 1);
+        let html = render("paste.rs", "/raw", &text, None);
+        assert!(html.contains(&format!("id=\"L{}\"", MAX_HIGHLIGHT_LINES + 1)));
+        assert!(!html.contains("<span style="));
+
+        let html = render("<script>.txt", "/raw", "<script>", Some("txt"));
+        assert!(html.contains("<h1>&lt;script&gt;.txt</h1>"));
+        assert!(!html.contains("<script>"));
+    }
+}
@@ -0,0 +1,117 @@
+use crate::backend::StorageBackend;
+use crate::store::{Bucket, StorageFile, UserDir};
+use crate::tus;
+use crate::Config;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Periodically deletes expired files, never returns.
+pub async fn run(config: Config, backend: Arc<dyn StorageBackend>) {
+    let mut interval = actix_web::rt::time::interval(Duration::from_secs(config.reaper_interval));
+    loop {
+        interval.tick().await;
+        reap(&config, backend.as_ref()).await;
+    }
+}
+
+/// Deletes all expired files of all users, together with buckets and user folders they leave empty,
+/// and all expired resumable uploads, giving back the quota unfinished ones reserved.
+///
+/// Buckets that are empty already are kept, they may be claimed by an upload still in progress.
+pub async fn reap(config: &Config, backend: &dyn StorageBackend) {
+    for info in tus::reap(backend).await {
+        // The full length of unfinished uploads was counted against the quota when they were created.
+        if let (Some(user_data), None) = (config.users.get(&info.username), &info.url) {
+            UserDir::new(backend, user_data).release(info.length);
+        }
+    }
+
+    for user_data in config.users.values() {
+        let user_dir = UserDir::new(backend, user_data);
+        if user_dir.open(false).await.is_none() {
+            continue;
+        }
+
+        for bucket in user_dir.buckets().await.unwrap_or_default() {
+            let bucket = match Bucket::new(&user_dir, Some(bucket)) {
+                Some(bucket) => bucket,
+                None => continue,
+            };
+
+            for filename in bucket.files().await.unwrap_or_default() {
+                let storage_file = StorageFile::new(&bucket, filename);
+                if storage_file.is_expired().await {
+                    println!(
+                        "Reaping expired File: {}/{}/{}",
+                        &user_data.folder, &bucket.name, &storage_file.name
+                    );
+                    if let Err(err) = storage_file.delete().await {
+                        println!("Could not reap File: {}", err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::open_backend;
+    use crate::config::BackendKind;
+    use crate::reaper::reap;
+    use crate::store::FileMeta;
+    use crate::tests::write_test_file;
+    use crate::{Bucket, Config, StorageFile, UserDir};
+
+    #[actix_web::test]
+    async fn reaps_expired_files() {
+        let config = Config {
+            backend: BackendKind::Memory,
+            ..Config::default()
+        };
+        let backend = open_backend(&config);
+        let (_, user_data) = *config.users.iter().peekable().peek().unwrap();
+        let user_dir = UserDir::new(backend.as_ref(), user_data);
+
+        let expired = Bucket::new(&user_dir, None).unwrap();
+        let expired_file = StorageFile::new(&expired, "expired.txt".into());
+        let kept = Bucket::new(&user_dir, None).unwrap();
+        let kept_file = StorageFile::new(&kept, "kept.txt".into());
+        for (storage_file, expires) in [(&expired_file, 1), (&kept_file, u64::MAX)] {
+            write_test_file(storage_file).await;
+            storage_file
+                .write_meta(&FileMeta {
+                    expires: Some(expires),
+                    ..FileMeta::default()
+                })
+                .await
+                .unwrap();
+        }
+
+        reap(&config, backend.as_ref()).await;
+
+        assert!(!expired_file.exists().await);
+        assert!(expired.open(false).await.is_none());
+        assert!(kept_file.exists().await);
+        assert_eq!(kept.files().await.unwrap(), vec!["kept.txt".to_string()]);
+    }
+
+    #[actix_web::test]
+    async fn keeps_empty_buckets() {
+        let config = Config {
+            backend: BackendKind::Memory,
+            ..Config::default()
+        };
+        let backend = open_backend(&config);
+        let (_, user_data) = *config.users.iter().peekable().peek().unwrap();
+        let user_dir = UserDir::new(backend.as_ref(), user_data);
+
+        // Claimed for an upload, which is not finished yet.
+        let claimed = Bucket::new(&user_dir, None).unwrap();
+        claimed.open(true).await.unwrap();
+
+        reap(&config, backend.as_ref()).await;
+
+        assert!(claimed.open(false).await.is_some());
+    }
+}
@@ -0,0 +1,66 @@
+use crate::Config;
+use serde_json::json;
+
+/// A ShareX custom uploader (`.sxcu`) uploading as `username` authenticated with `key`.
+pub fn custom_uploader(config: &Config, username: &str, key: &str) -> serde_json::Value {
+    let public_url = config.public_url();
+
+    json!({
+        "Version": "15.0.0",
+        "Name": format!("share.rs ({})", username),
+        "DestinationType": "ImageUploader, TextUploader, FileUploader",
+        "RequestMethod": "POST",
+        "RequestURL": format!("{}/", public_url),
+        "Parameters": {
+            "format": "json"
+        },
+        "Headers": {
+            "username": username,
+            "auth": key
+        },
+        "Body": "MultipartFormData",
+        "FileFormName": "file",
+        "URL": "{json:[0].url}",
+        "ThumbnailURL": "{json:[0].thumbnail_url}",
+        "DeletionURL": "{json:[0].deletion_url}",
+        "ErrorMessage": "{response}"
+    })
+}
+
+/// The name the custom uploader is saved as.
+pub fn file_name(config: &Config, username: &str) -> String {
+    let host = config
+        .public_url()
+        .split("://")
+        .last()
+        .unwrap_or_default()
+        .replace(|char: char| !char.is_alphanumeric() && char != '.', "_");
+
+    format!("{} ({}).sxcu", host, username)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sharex::{custom_uploader, file_name};
+    use crate::Config;
+
+    #[test]
+    fn test_custom_uploader() {
+        let config = Config {
+            public_url: Some("https://share.example.com".to_string()),
+            ..Config::default()
+        };
+        let uploader = custom_uploader(&config, "user1", "mysecret");
+
+        assert_eq!(uploader["RequestURL"], "https://share.example.com/");
+        assert_eq!(uploader["Parameters"]["format"], "json");
+        assert_eq!(uploader["Headers"]["username"], "user1");
+        assert_eq!(uploader["Headers"]["auth"], "mysecret");
+        assert_eq!(uploader["FileFormName"], "file");
+        assert_eq!(uploader["DeletionURL"], "{json:[0].deletion_url}");
+        assert_eq!(
+            file_name(&config, "user1"),
+            "share.example.com (user1).sxcu"
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::backend::StorageBackend;
+use actix_web::web::Bytes;
+use futures::TryStreamExt;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+/// Short links are served as `/s/<id>`, each id is kept as `.links/<id>` naming the bucket of the link.
+const LINKS_DIR: &str = ".links";
+const ID_LEN: usize = 6;
+/// Attempts to find an unused id, only a full id space runs out.
+const ID_ATTEMPTS: usize = 16;
+
+/// The bucket a short id points to, the link itself is stored there as a file.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct LinkTarget {
+    pub username: String,
+    pub bucket: String,
+}
+
+/// Validates an id from a request, to never leave the links directory.
+fn is_valid(id: &str) -> bool {
+    id.len() == ID_LEN && id.chars().all(|char| char.is_ascii_alphanumeric())
+}
+
+fn key(id: &str) -> String {
+    format!("{}/{}", LINKS_DIR, id)
+}
+
+/// Stores `target` under a new random id and returns the id.
+pub async fn create(backend: &dyn StorageBackend, target: &LinkTarget) -> io::Result<String> {
+    backend.create_dir(LINKS_DIR).await?;
+    for _ in 0..ID_ATTEMPTS {
+        let id: String = rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(ID_LEN)
+            .map(char::from)
+            .collect();
+        let mut writer = match backend.create(&key(&id)).await {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => result?,
+        };
+        writer
+            .write(Bytes::from(serde_json::to_vec(target)?))
+            .await?;
+        writer.finish().await?;
+        return Ok(id);
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "No unused short link id found",
+    ))
+}
+
+/// The bucket of the short link `id`, if there is one.
+pub async fn resolve(backend: &dyn StorageBackend, id: &str) -> Option<LinkTarget> {
+    if !is_valid(id) {
+        return None;
+    }
+
+    let object = backend.open(&key(id), None).await.ok()?;
+    let content: Vec<Bytes> = object.body.try_collect().await.ok()?;
+    serde_json::from_slice(&content.concat()).ok()
+}
+
+pub async fn delete(backend: &dyn StorageBackend, id: &str) -> io::Result<()> {
+    if is_valid(id) {
+        backend.delete(&key(id)).await
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::open_backend;
+    use crate::shortlink::{create, delete, resolve, LinkTarget, ID_LEN};
+    use crate::tests::memory_config;
+
+    #[actix_web::test]
+    async fn test_create_resolve() {
+        let backend = open_backend(&memory_config());
+        let target = LinkTarget {
+            username: "user".into(),
+            bucket: "bucket".into(),
+        };
+
+        let id = create(backend.as_ref(), &target).await.unwrap();
+        assert_eq!(id.len(), ID_LEN);
+        assert_eq!(resolve(backend.as_ref(), &id).await, Some(target.clone()));
+        assert_ne!(create(backend.as_ref(), &target).await.unwrap(), id);
+
+        delete(backend.as_ref(), &id).await.unwrap();
+        assert_eq!(resolve(backend.as_ref(), &id).await, None);
+        assert_eq!(resolve(backend.as_ref(), "../.tus").await, None);
+    }
+}
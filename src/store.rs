@@ -1,63 +1,160 @@
-use crate::{Config, UserData};
-use actix_files::NamedFile;
-use actix_multipart::Field;
-use actix_web::error::{ErrorInternalServerError, ErrorNotFound};
-use actix_web::{web, HttpRequest, HttpResponse};
-use futures::StreamExt;
+use crate::backend::{hex, Metadata, Object, ObjectWriter, StorageBackend};
+use crate::config::{BucketNames, Limits};
+use crate::shortlink;
+use crate::tus;
+use crate::UserData;
+use actix_files::{HttpRange, NamedFile};
+use actix_web::error::{
+    ErrorBadRequest, ErrorConflict, ErrorInternalServerError, ErrorNotFound, ErrorPayloadTooLarge,
+};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse};
+use futures::{Stream, StreamExt, TryStreamExt};
 use rand::Rng;
-use std::fs;
+use ring::digest;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bucket names taken by other routes, e.g. `DELETE /tus/{id}` would shadow deleting a file of bucket `tus`.
+const RESERVED_BUCKETS: &[&str] = &["tus"];
+/// Attempts to find an unused random bucket name, only short names run out.
+const RANDOM_NAME_ATTEMPTS: usize = 16;
+
+/// Bytes stored in a user folder, counted once and kept up to date by uploads and deletions.
+#[derive(Clone, Default)]
+pub struct Usage(Arc<Mutex<Option<u64>>>);
+
+impl Usage {
+    /// Counts `size` more bytes, unless that exceeds `quota`.
+    fn reserve(&self, size: u64, quota: Option<u64>) -> bool {
+        let mut usage = self.0.lock().unwrap();
+        let current = usage.unwrap_or(0);
+        if quota.map(|quota| current + size > quota).unwrap_or(false) {
+            return false;
+        }
+        if let Some(usage) = usage.as_mut() {
+            *usage += size;
+        }
+
+        true
+    }
+
+    fn release(&self, size: u64) {
+        if let Some(usage) = self.0.lock().unwrap().as_mut() {
+            *usage = usage.saturating_sub(size);
+        }
+    }
+}
 
 pub struct UserDir<'a, 'b> {
-    config: &'a Config,
+    backend: &'a dyn StorageBackend,
     user_data: &'b UserData,
+    limits: Limits,
+    dedup: bool,
+    bucket_names: Option<&'b BucketNames>,
 }
 
 impl<'a, 'b> UserDir<'a, 'b> {
-    pub fn new(config: &'a Config, user_data: &'b UserData) -> Self {
-        UserDir { config, user_data }
+    pub fn new(backend: &'a dyn StorageBackend, user_data: &'b UserData) -> Self {
+        UserDir {
+            backend,
+            user_data,
+            limits: Limits::default(),
+            dedup: false,
+            bucket_names: None,
+        }
     }
 
-    pub fn path(&self) -> PathBuf {
-        Path::new(&self.config.storage_folder).join(&self.user_data.folder)
+    /// Enforces `limits` on the files written to the folder.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 
-    pub async fn open(&self, create: bool) -> Option<PathBuf> {
-        let path = self.path();
-        if create {
-            let path = path.clone();
-            web::block(move || fs::create_dir(&path))
-                .await
-                .unwrap_or(Ok(()))
-                .unwrap_or(());
+    /// Stores the content of files written to the folder in the blob store, once per content.
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Generates the names of new buckets with `bucket_names`, instead of 16 letters and digits.
+    pub fn with_bucket_names(mut self, bucket_names: &'b BucketNames) -> Self {
+        self.bucket_names = Some(bucket_names);
+        self
+    }
+
+    /// Bytes stored in the folder and reserved by unfinished resumable uploads, only walking it the first time.
+    pub async fn usage(&self) -> Result<u64, actix_web::error::Error> {
+        if let Some(usage) = *self.user_data.usage.0.lock().unwrap() {
+            return Ok(usage);
         }
 
-        let exists = {
-            let path = path.clone();
-            web::block(move || path.exists()).await.unwrap()
-        };
-        if exists {
-            Some(path)
+        let mut usage = tus::reserved(self.backend, &self.key()).await?;
+        if self.open(false).await.is_some() {
+            for bucket in self.buckets().await? {
+                if let Some(bucket) = Bucket::new(self, Some(bucket)) {
+                    for filename in bucket.files().await? {
+                        if let Ok(metadata) = StorageFile::new(&bucket, filename).metadata().await {
+                            usage += metadata.size;
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(*self.user_data.usage.0.lock().unwrap().get_or_insert(usage))
+    }
+
+    /// Counts `size` bytes which are not stored as a file yet, fails with 413 if that exceeds the quota.
+    pub async fn reserve(&self, size: u64) -> Result<(), actix_web::error::Error> {
+        if self.limits.quota_bytes.is_some() {
+            self.usage().await?;
+        }
+        if self.user_data.usage.reserve(size, self.limits.quota_bytes) {
+            Ok(())
+        } else {
+            Err(ErrorPayloadTooLarge("Quota exceeded"))
+        }
+    }
+
+    /// Gives back bytes counted by [`UserDir::reserve`].
+    pub fn release(&self, size: u64) {
+        self.user_data.usage.release(size);
+    }
+
+    pub fn key(&self) -> String {
+        self.user_data.folder.clone()
+    }
+
+    pub async fn open(&self, create: bool) -> Option<String> {
+        let key = self.key();
+        if create {
+            self.backend.create_dir(&key).await.ok()?;
+
+            Some(key)
+        } else if self.backend.exists(&key).await.unwrap_or(false) {
+            Some(key)
         } else {
             None
         }
     }
 
+    pub async fn buckets(&self) -> Result<Vec<String>, actix_web::error::Error> {
+        Ok(self.backend.list(&self.key()).await?)
+    }
+
     pub async fn try_delete(&self) -> Result<(), actix_web::error::Error> {
-        if let Some(path) = self.open(false).await {
-            let files = {
-                let path = path.clone();
-                web::block(move || fs::read_dir(&path))
-                    .await
-                    .unwrap()?
-                    .count()
-            };
+        if let Some(key) = self.open(false).await {
+            let files = self.backend.list(&key).await?.len();
             if files == 0 {
-                web::block(move || fs::remove_dir(&path))
+                self.backend
+                    .remove_dir(&key)
                     .await
-                    .unwrap()
                     .map_err(|_| ErrorInternalServerError("Can't Delete User Dir"))
             } else {
                 Ok(())
@@ -84,55 +181,100 @@ impl<'a, 'b, 'c> Bucket<'a, 'b, 'c> {
         } else {
             Some(Bucket {
                 user_dir,
-                name: String::from_utf8(
-                    rand::thread_rng()
-                        .sample_iter(rand::distributions::Alphanumeric)
-                        .take(16)
-                        .collect(),
-                )
-                .unwrap(),
+                name: match user_dir.bucket_names {
+                    Some(bucket_names) => bucket_names.generate(),
+                    None => BucketNames::default().generate(),
+                },
             })
         }
     }
 
-    pub async fn open(&self, create: bool) -> Option<PathBuf> {
-        if let Some(path) = self.user_dir.open(create).await {
-            let path = path.join(&self.name);
-            if create {
-                let path = path.clone();
-                web::block(move || fs::create_dir(&path))
-                    .await
-                    .unwrap_or(Ok(()))
-                    .unwrap_or(());
+    /// A new bucket with a random name no bucket of the user has yet.
+    pub async fn random(user_dir: &'a UserDir<'b, 'c>) -> Result<Self, actix_web::error::Error> {
+        for _ in 0..RANDOM_NAME_ATTEMPTS {
+            let bucket = Bucket::new(user_dir, None).unwrap();
+            if bucket.is_reserved() {
+                continue;
+            }
+            match bucket.create_new().await {
+                Ok(()) => return Ok(bucket),
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
             }
+        }
 
-            let exists = {
-                let path = path.clone();
-                web::block(move || path.exists()).await.unwrap()
-            };
-            if exists {
-                Some(path)
-            } else {
-                None
+        Err(ErrorInternalServerError("No unused bucket name found"))
+    }
+
+    /// A new bucket named by the client, fails with 400 for invalid or reserved names and 409 if it exists.
+    pub async fn claim(
+        user_dir: &'a UserDir<'b, 'c>,
+        name: String,
+    ) -> Result<Self, actix_web::error::Error> {
+        let bucket = Bucket::new(user_dir, Some(name))
+            .filter(|bucket| !bucket.name.is_empty() && !bucket.is_reserved())
+            .ok_or_else(|| ErrorBadRequest("Invalid bucket name"))?;
+        match bucket.create_new().await {
+            Ok(()) => Ok(bucket),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                Err(ErrorConflict("Bucket already exists"))
             }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Creates the bucket, so of two requests picking the same name only one gets it.
+    async fn create_new(&self) -> std::io::Result<()> {
+        if self.user_dir.open(true).await.is_none() {
+            return Err(std::io::Error::other("Can't create the user folder"));
+        }
+
+        self.user_dir.backend.create_new_dir(&self.key()).await
+    }
+
+    pub fn is_reserved(&self) -> bool {
+        RESERVED_BUCKETS.contains(&self.name.as_str())
+    }
+
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.user_dir.key(), self.name)
+    }
+
+    pub async fn open(&self, create: bool) -> Option<String> {
+        self.user_dir.open(create).await?;
+
+        let key = self.key();
+        if create {
+            self.user_dir.backend.create_dir(&key).await.ok()?;
+
+            Some(key)
+        } else if self.user_dir.backend.exists(&key).await.unwrap_or(false) {
+            Some(key)
         } else {
             None
         }
     }
 
+    /// The names of all files in the bucket, without their sidecar files.
+    pub async fn files(&self) -> Result<Vec<String>, actix_web::error::Error> {
+        Ok(self
+            .user_dir
+            .backend
+            .list(&self.key())
+            .await?
+            .into_iter()
+            .filter(|name| !name.starts_with('.'))
+            .collect())
+    }
+
     pub async fn try_delete(&self) -> Result<(), actix_web::error::Error> {
-        if let Some(path) = self.open(false).await {
-            let files = {
-                let path = path.clone();
-                web::block(move || fs::read_dir(&path))
-                    .await
-                    .unwrap()?
-                    .count()
-            };
+        if let Some(key) = self.open(false).await {
+            let files = self.user_dir.backend.list(&key).await?.len();
             if files == 0 {
-                web::block(move || fs::remove_dir(&path))
+                self.user_dir
+                    .backend
+                    .remove_dir(&key)
                     .await
-                    .unwrap()
                     .map_err(|_| ErrorInternalServerError("Can't Delete Bucket"))?;
 
                 self.user_dir.try_delete().await
@@ -145,88 +287,549 @@ impl<'a, 'b, 'c> Bucket<'a, 'b, 'c> {
     }
 }
 
+/// Stored next to every file as `.<filename>.meta` in the bucket.
+///
+/// Files uploaded before a field was added do not have it, so all of them are optional.
+#[derive(Deserialize, Serialize, Default, Clone, PartialEq, Debug)]
+pub struct FileMeta {
+    /// Unix timestamp after which the file is no longer served and gets reaped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<u64>,
+    /// SHA-256 of the deduplicated content, which is stored once in the blob store.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    /// The name of the user who uploaded the file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    /// Unix timestamp of the upload.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    /// The filename sent by the client, before it was sanitized.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_name: Option<String>,
+    /// The content type sent by the client, the file is still served by its extension.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Hex encoded SHA-256 of the content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// The language of a paste, used for its syntax highlighting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// The target of a short link, the file redirects there instead of being served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redirect: Option<String>,
+    /// The id of a short link served as `/s/<id>`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_link: Option<String>,
+}
+
+impl FileMeta {
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.expires.map(|expires| expires <= now).unwrap_or(false)
+    }
+}
+
+/// Deduplicated content is stored once as `.blobs/<sha256>`, every file referencing it has an
+/// empty marker in `.blobs/<sha256>.refs`. The blob is deleted with its last reference.
+const BLOBS: &str = ".blobs";
+
+/// Held while adding or removing references, so a blob is never deleted while it gains one.
+/// The lock only covers this process, deduplication on a shared S3 bucket needs a single instance.
+static BLOB_REFS: futures::lock::Mutex<()> = futures::lock::Mutex::new(());
+
+/// Held while counting a click, so concurrent clicks on a short link are not lost.
+static CLICKS: futures::lock::Mutex<()> = futures::lock::Mutex::new(());
+
+fn blob_key(sha256: &str) -> String {
+    format!("{}/{}", BLOBS, sha256)
+}
+
+fn refs_key(sha256: &str) -> String {
+    format!("{}/{}.refs", BLOBS, sha256)
+}
+
+/// A random key in the blob store to write new content to, before it is known under which hash it is stored.
+fn staging_key() -> String {
+    format!(
+        "{}/.{}",
+        BLOBS,
+        rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect::<String>()
+    )
+}
+
+fn hex_sha256(content: &[u8]) -> String {
+    hex(digest::digest(&digest::SHA256, content).as_ref())
+}
+
+/// Another upload created the file first, which the S3 backend may only notice when finishing the write.
+fn already_exists(err: std::io::Error) -> actix_web::error::Error {
+    if err.kind() == std::io::ErrorKind::AlreadyExists {
+        ErrorConflict("File already exists")
+    } else {
+        err.into()
+    }
+}
+
+/// Keeps uploaded pages and images from running scripts on this origin, e.g. reading the key the pages store.
+fn sandboxed(mut response: HttpResponse) -> HttpResponse {
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        header::HeaderValue::from_static("sandbox"),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    response
+}
+
+/// What [`StorageFile::write`] wrote.
+pub struct Written {
+    pub size: u64,
+    /// Hex encoded SHA-256 of the content.
+    pub sha256: String,
+    /// Unix timestamp of the upload, as recorded in its `FileMeta`.
+    pub created: u64,
+}
+
 pub struct StorageFile<'a, 'b, 'c, 'd> {
     bucket: &'a Bucket<'b, 'c, 'd>,
     pub name: String,
 }
 
 impl<'a, 'b, 'c, 'd> StorageFile<'a, 'b, 'c, 'd> {
+    /// Names starting with a dot are reserved for sidecar files, so leading dots are stripped.
     pub fn new(bucket: &'a Bucket<'b, 'c, 'd>, name: String) -> Self {
         StorageFile {
             bucket,
-            name: sanitize_filename::sanitize(name),
+            name: Self::sanitize(&name),
         }
     }
 
-    pub async fn open(&self, create: bool) -> Option<File> {
-        if let Some(path) = self.bucket.open(create).await {
-            let path = path.join(&self.name);
+    /// The name a file named `name` is stored as, empty if nothing usable is left.
+    pub fn sanitize(name: &str) -> String {
+        sanitize_filename::sanitize(name)
+            .trim_start_matches('.')
+            .to_string()
+    }
+
+    fn backend(&self) -> &dyn StorageBackend {
+        self.bucket.user_dir.backend
+    }
 
-            let exists = {
-                let path = path.clone();
-                web::block(move || path.exists()).await.unwrap()
-            };
-            if exists {
-                if create {
-                    None
-                } else {
-                    web::block(move || File::open(&path))
-                        .await
-                        .unwrap()
-                        .map(Some)
-                        .unwrap_or(None)
+    pub fn key(&self) -> String {
+        format!("{}/{}", self.bucket.key(), self.name)
+    }
+
+    fn meta_key(&self) -> String {
+        format!("{}/.{}.meta", self.bucket.key(), self.name)
+    }
+
+    fn clicks_key(&self) -> String {
+        format!("{}/.{}.clicks", self.bucket.key(), self.name)
+    }
+
+    fn thumbnail_key(&self) -> String {
+        format!("{}/.{}.thumb", self.bucket.key(), self.name)
+    }
+
+    pub async fn exists(&self) -> bool {
+        self.backend().exists(&self.key()).await.unwrap_or(false)
+    }
+
+    /// The key the content is stored at, in the blob store if the file is deduplicated.
+    pub async fn content_key(&self) -> String {
+        match self.meta().await.blob {
+            Some(sha256) => blob_key(&sha256),
+            None => self.key(),
+        }
+    }
+
+    pub async fn metadata(&self) -> std::io::Result<Metadata> {
+        self.backend().metadata(&self.content_key().await).await
+    }
+
+    async fn read_sidecar(&self, key: &str) -> Option<Vec<u8>> {
+        let object = self.backend().open(key, None).await.ok()?;
+        let content: Vec<Bytes> = object.body.try_collect().await.ok()?;
+        Some(content.concat())
+    }
+
+    async fn write_sidecar(
+        &self,
+        key: &str,
+        content: Bytes,
+    ) -> Result<(), actix_web::error::Error> {
+        if self.backend().exists(key).await? {
+            self.backend().delete(key).await?;
+        }
+
+        let mut writer = self.backend().create(key).await?;
+        writer.write(content).await?;
+        Ok(writer.finish().await?)
+    }
+
+    pub async fn meta(&self) -> FileMeta {
+        self.read_sidecar(&self.meta_key())
+            .await
+            .and_then(|content| serde_json::from_slice(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// How often the short link was followed.
+    pub async fn clicks(&self) -> u64 {
+        self.read_sidecar(&self.clicks_key())
+            .await
+            .and_then(|content| String::from_utf8(content).ok())
+            .and_then(|clicks| clicks.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    async fn count_click(&self) -> Result<(), actix_web::error::Error> {
+        let _clicks = CLICKS.lock().await;
+        let clicks = self.clicks().await + 1;
+        self.write_sidecar(&self.clicks_key(), Bytes::from(clicks.to_string()))
+            .await
+    }
+
+    /// The cached thumbnail, if one was generated before.
+    pub async fn thumbnail(&self) -> Option<Vec<u8>> {
+        self.read_sidecar(&self.thumbnail_key()).await
+    }
+
+    pub async fn write_thumbnail(&self, thumbnail: &[u8]) -> Result<(), actix_web::error::Error> {
+        self.write_sidecar(&self.thumbnail_key(), Bytes::copy_from_slice(thumbnail))
+            .await
+    }
+
+    pub async fn write_meta(&self, meta: &FileMeta) -> Result<(), actix_web::error::Error> {
+        self.write_sidecar(&self.meta_key(), Bytes::from(serde_json::to_vec(meta)?))
+            .await
+    }
+
+    pub async fn is_expired(&self) -> bool {
+        self.meta().await.is_expired()
+    }
+
+    pub async fn create(&self) -> Option<Box<dyn ObjectWriter>> {
+        self.bucket.open(true).await?;
+        self.backend().create(&self.key()).await.ok()
+    }
+
+    pub async fn open(&self, range: Option<Range<u64>>) -> Option<Object> {
+        self.backend()
+            .open(&self.content_key().await, range)
+            .await
+            .ok()
+    }
+
+    /// The content type the file is served with, guessed from its extension.
+    pub fn content_type(&self) -> mime::Mime {
+        Path::new(&self.name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(actix_files::file_extension_to_mime)
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+    }
+
+    /// Streams the content into the file, on any error the partial file is removed again.
+    /// `meta` is recorded in the sidecar file, together with the size, hash and time of the upload.
+    ///
+    /// Fails with 413 if the file exceeds the `max_file_size` or the `quota_bytes` of the user.
+    pub async fn write<S, E>(
+        &self,
+        mut stream: S,
+        meta: FileMeta,
+    ) -> Result<Written, actix_web::error::Error>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: Into<actix_web::error::Error>,
+    {
+        let user_dir = self.bucket.user_dir;
+        let limits = user_dir.limits;
+        let usage = &user_dir.user_data.usage;
+        if limits.quota_bytes.is_some() {
+            user_dir.usage().await?;
+        }
+
+        let staging = user_dir.dedup.then(staging_key);
+        if staging.is_some() && self.exists().await {
+            // Without dedup the backend notices when the write finishes, with it only the blob would be written.
+            return Err(ErrorConflict("File already exists"));
+        }
+        let writer = match &staging {
+            Some(staging) => {
+                self.backend().create_dir(BLOBS).await?;
+                self.backend().create(staging).await.ok()
+            }
+            None => self.create().await,
+        };
+
+        if let Some(mut writer) = writer {
+            let mut size = 0;
+            let mut sha256 = digest::Context::new(&digest::SHA256);
+            let result = async {
+                while let Some(chunk) = stream.next().await {
+                    let chunk = chunk.map_err(Into::into)?;
+                    if limits
+                        .max_file_size
+                        .map(|max| size + chunk.len() as u64 > max)
+                        .unwrap_or(false)
+                    {
+                        return Err(ErrorPayloadTooLarge("File too large"));
+                    }
+                    if !usage.reserve(chunk.len() as u64, limits.quota_bytes) {
+                        return Err(ErrorPayloadTooLarge("Quota exceeded"));
+                    }
+                    size += chunk.len() as u64;
+                    sha256.update(&chunk);
+                    writer.write(chunk).await?;
                 }
-            } else if create {
-                web::block(move || File::create(&path))
-                    .await
-                    .unwrap()
-                    .map(Some)
-                    .unwrap_or(None)
-            } else {
-                None
+
+                Ok(())
+            }
+            .await;
+            let result = match result {
+                Ok(()) => writer.finish().await.map_err(already_exists),
+                Err(err) => {
+                    drop(writer);
+                    Err(err)
+                }
+            };
+            let sha256 = hex(sha256.finish().as_ref());
+            let result = match result {
+                Ok(()) => self.record(staging.as_deref(), size, sha256, meta).await,
+                Err(err) => Err(err),
+            };
+            if result.is_err() {
+                usage.release(size);
+                self.bucket.try_delete().await.ok();
             }
+
+            result
         } else {
-            None
+            Err(ErrorInternalServerError("Cant write to file"))
         }
     }
 
-    pub async fn open_path(&self, create: bool) -> Option<PathBuf> {
-        if let Some(path) = self.bucket.open(create).await {
-            let path = path.join(&self.name);
+    /// Moves the complete content stored at `key` into the file, instead of copying it like `write`.
+    /// It is only read to hash it, on success `key` is gone.
+    ///
+    /// Fails with 413 if the file exceeds the `max_file_size` or the `quota_bytes` of the user.
+    pub async fn write_moved(
+        &self,
+        key: &str,
+        meta: FileMeta,
+    ) -> Result<Written, actix_web::error::Error> {
+        let user_dir = self.bucket.user_dir;
+        let limits = user_dir.limits;
+        let usage = &user_dir.user_data.usage;
+        if limits.quota_bytes.is_some() {
+            user_dir.usage().await?;
+        }
 
-            let exists = {
-                let path = path.clone();
-                web::block(move || path.exists()).await.unwrap()
-            };
-            if exists {
-                Some(path)
-            } else {
-                None
+        let mut object = self.backend().open(key, None).await?;
+        let size = object.size;
+        if limits.max_file_size.is_some_and(|max| size > max) {
+            return Err(ErrorPayloadTooLarge("File too large"));
+        }
+        let mut sha256 = digest::Context::new(&digest::SHA256);
+        while let Some(chunk) = object.body.next().await {
+            sha256.update(&chunk?);
+        }
+        let sha256 = hex(sha256.finish().as_ref());
+        if !usage.reserve(size, limits.quota_bytes) {
+            return Err(ErrorPayloadTooLarge("Quota exceeded"));
+        }
+
+        let staging = user_dir.dedup.then(staging_key);
+        let result = async {
+            match &staging {
+                Some(_) if self.exists().await => return Err(ErrorConflict("File already exists")),
+                Some(staging) => {
+                    self.backend().create_dir(BLOBS).await?;
+                    self.backend().rename(key, staging).await?;
+                }
+                None => {
+                    self.bucket
+                        .open(true)
+                        .await
+                        .ok_or_else(|| ErrorInternalServerError("Cant write to file"))?;
+                    self.backend()
+                        .rename(key, &self.key())
+                        .await
+                        .map_err(already_exists)?;
+                }
             }
-        } else {
-            None
+            self.record(staging.as_deref(), size, sha256, meta).await
+        }
+        .await;
+        if result.is_err() {
+            usage.release(size);
+            self.bucket.try_delete().await.ok();
         }
+
+        result
     }
 
-    pub async fn write(&self, field: &mut Field) -> Result<(), actix_web::error::Error> {
-        if let Some(mut file) = self.open(true).await {
-            while let Some(chunk) = field.next().await {
-                let data = chunk.unwrap();
-                file = web::block(move || file.write_all(&data).map(|_| file)).await??;
+    /// Records `meta` for the content just written, with dedup it is moved from `staging` into the blob store.
+    /// On failure the file is removed again.
+    async fn record(
+        &self,
+        staging: Option<&str>,
+        size: u64,
+        sha256: String,
+        meta: FileMeta,
+    ) -> Result<Written, actix_web::error::Error> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let meta = FileMeta {
+            created: Some(created),
+            size: Some(size),
+            sha256: Some(sha256.clone()),
+            ..meta
+        };
+        match staging {
+            Some(staging) => self.link_blob(staging, &sha256, meta).await?,
+            None => {
+                if let Err(err) = self.write_meta(&meta).await {
+                    self.backend().delete(&self.key()).await.ok();
+                    return Err(err);
+                }
             }
+        }
 
-            Ok(())
-        } else {
-            Err(ErrorInternalServerError("Cant write to file"))
+        Ok(Written {
+            size,
+            sha256,
+            created,
+        })
+    }
+
+    /// Moves the content written to `staging` into the blob store, unless it is already there,
+    /// and makes the file a reference to it.
+    ///
+    /// The file is created first, so of two uploads of the same name only one gets it.
+    async fn link_blob(
+        &self,
+        staging: &str,
+        sha256: &str,
+        meta: FileMeta,
+    ) -> Result<(), actix_web::error::Error> {
+        let backend = self.backend();
+        let claimed = async {
+            self.bucket
+                .open(true)
+                .await
+                .ok_or_else(|| ErrorInternalServerError("Cant write to file"))?;
+            let writer = backend.create(&self.key()).await.map_err(already_exists)?;
+            writer.finish().await.map_err(already_exists)
+        }
+        .await;
+        if let Err(err) = claimed {
+            backend.delete(staging).await.ok();
+            return Err(err);
+        }
+
+        let reference = format!("{}/{}", refs_key(sha256), hex_sha256(self.key().as_bytes()));
+        {
+            let _refs = BLOB_REFS.lock().await;
+            let result = async {
+                match backend.rename(staging, &blob_key(sha256)).await {
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                        backend.delete(staging).await?
+                    }
+                    result => result?,
+                }
+                backend.create_dir(&refs_key(sha256)).await?;
+                backend.create(&reference).await?.finish().await
+            }
+            .await;
+            if let Err(err) = result {
+                backend.delete(staging).await.ok();
+                self.unlink_blob(sha256).await;
+                backend.delete(&self.key()).await.ok();
+                return Err(err.into());
+            }
+        }
+
+        let result = self
+            .write_meta(&FileMeta {
+                blob: Some(sha256.to_string()),
+                ..meta
+            })
+            .await;
+        if result.is_err() {
+            let _refs = BLOB_REFS.lock().await;
+            self.unlink_blob(sha256).await;
+            backend.delete(&self.key()).await.ok();
+            backend.delete(&self.meta_key()).await.ok();
+        }
+
+        result
+    }
+
+    /// Removes the reference of the file to the blob, deleting the blob if it was the last one.
+    ///
+    /// Must be called while holding [`BLOB_REFS`].
+    async fn unlink_blob(&self, sha256: &str) {
+        let backend = self.backend();
+        let refs = refs_key(sha256);
+        let reference = format!("{}/{}", refs, hex_sha256(self.key().as_bytes()));
+        backend.delete(&reference).await.ok();
+        if backend
+            .list(&refs)
+            .await
+            .map(|references| references.is_empty())
+            .unwrap_or(true)
+        {
+            backend.remove_dir(&refs).await.ok();
+            backend.delete(&blob_key(sha256)).await.ok();
         }
     }
 
     pub async fn delete(&self) -> Result<(), actix_web::error::Error> {
-        if let Some(path) = self.open_path(false).await {
-            web::block(move || fs::remove_file(&path))
+        if self.exists().await {
+            let size = self
+                .metadata()
+                .await
+                .map(|metadata| metadata.size)
+                .unwrap_or(0);
+            let meta = self.meta().await;
+            self.backend()
+                .delete(&self.key())
                 .await
-                .unwrap()
                 .map_err(|_| ErrorInternalServerError("File Can not ne deleted"))?;
+            self.bucket.user_dir.user_data.usage.release(size);
+            if let Some(sha256) = meta.blob {
+                let _refs = BLOB_REFS.lock().await;
+                self.unlink_blob(&sha256).await;
+            }
+            if let Some(id) = meta.short_link {
+                shortlink::delete(self.backend(), &id).await.ok();
+            }
+            for sidecar in [self.meta_key(), self.clicks_key(), self.thumbnail_key()] {
+                if self.backend().exists(&sidecar).await? {
+                    self.backend()
+                        .delete(&sidecar)
+                        .await
+                        .map_err(|_| ErrorInternalServerError("File Can not ne deleted"))?;
+                }
+            }
 
             self.bucket.try_delete().await
         } else {
@@ -234,10 +837,75 @@ impl<'a, 'b, 'c, 'd> StorageFile<'a, 'b, 'c, 'd> {
         }
     }
 
+    /// Serves the content, or redirects to the target of a short link and counts the click.
     pub async fn serve(&self, req: &HttpRequest) -> HttpResponse {
-        if let Some(path) = self.open_path(false).await {
-            if let Ok(response) = NamedFile::open(path).map(|file| file.into_response(req)) {
-                response
+        let meta = self.meta().await;
+        if !self.exists().await || meta.is_expired() {
+            return HttpResponse::NotFound().finish();
+        }
+        if let Some(target) = meta.redirect {
+            if let Err(error) = self.count_click().await {
+                eprintln!("Could not count the click on {}: {}", self.key(), error);
+            }
+            return HttpResponse::Found()
+                .insert_header((header::LOCATION, target))
+                .finish();
+        }
+
+        let content_key = self.content_key().await;
+        if let Some(path) = self.backend().local_path(&content_key) {
+            // Named after the file, as the content of deduplicated files is stored under its hash.
+            if let Ok(response) = File::open(path)
+                .and_then(|file| NamedFile::from_file(file, &self.name))
+                .map(|file| file.into_response(req))
+            {
+                sandboxed(response)
+            } else {
+                HttpResponse::NotFound().finish()
+            }
+        } else if let Ok(metadata) = self.backend().metadata(&content_key).await {
+            let mime = self.content_type();
+            let size = metadata.size;
+
+            let range = match req
+                .headers()
+                .get(header::RANGE)
+                .and_then(|range| range.to_str().ok())
+            {
+                Some(range) => match HttpRange::parse(range, size) {
+                    Ok(ranges) => ranges
+                        .first()
+                        .map(|range| range.start..range.start + range.length),
+                    Err(_) => {
+                        return HttpResponse::RangeNotSatisfiable()
+                            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                            .finish()
+                    }
+                },
+                None => None,
+            };
+
+            if let Some(object) = self.open(range.clone()).await {
+                let mut response = match &range {
+                    Some(range) => {
+                        let mut response = HttpResponse::PartialContent();
+                        response.insert_header((
+                            header::CONTENT_RANGE,
+                            format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+                        ));
+                        response
+                    }
+                    None => HttpResponse::Ok(),
+                };
+
+                sandboxed(
+                    response
+                        .content_type(mime)
+                        .insert_header((header::ACCEPT_RANGES, "bytes"))
+                        .insert_header(header::LastModified(metadata.modified.into()))
+                        .no_chunking(object.size)
+                        .streaming(object.body),
+                )
             } else {
                 HttpResponse::NotFound().finish()
             }
@@ -246,3 +914,278 @@ impl<'a, 'b, 'c, 'd> StorageFile<'a, 'b, 'c, 'd> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{FilesystemBackend, MemoryBackend, StorageBackend};
+    use crate::config::{BucketNames, Limits};
+    use crate::store::FileMeta;
+    use crate::tests::write_test_file;
+    use crate::{Bucket, StorageFile, UserData, UserDir};
+    use actix_web::http::StatusCode;
+    use actix_web::web::Bytes;
+    use futures::TryStreamExt;
+
+    async fn round_trip(backend: &dyn StorageBackend) {
+        let user_data = UserData::default();
+        let user_dir = UserDir::new(backend, &user_data);
+        let bucket = Bucket::new(&user_dir, None).unwrap();
+        let storage_file = StorageFile::new(&bucket, "file.txt".into());
+        let written = storage_file
+            .write(
+                futures::stream::iter([
+                    Ok::<_, actix_web::Error>(Bytes::from_static(b"This is ")),
+                    Ok(Bytes::from_static(b"a testfile!")),
+                ]),
+                FileMeta {
+                    uploader: Some("user1".to_string()),
+                    original_name: Some("../file.txt".to_string()),
+                    ..FileMeta::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(written.size, 19);
+        assert_eq!(
+            written.sha256,
+            "2203f2f8e854923946eccbaca2f9ed70ecc6d94fca5f07232a91e488c319bd0b"
+        );
+
+        assert!(storage_file.exists().await);
+        assert!(storage_file.create().await.is_none());
+        let mut entries = backend.list(&bucket.key()).await.unwrap();
+        entries.sort();
+        assert_eq!(
+            entries,
+            vec![".file.txt.meta".to_string(), "file.txt".to_string()]
+        );
+        assert_eq!(bucket.files().await.unwrap(), vec!["file.txt".to_string()]);
+
+        let meta = storage_file.meta().await;
+        assert_eq!(meta.uploader.as_deref(), Some("user1"));
+        assert_eq!(meta.original_name.as_deref(), Some("../file.txt"));
+        assert_eq!(meta.size, Some(19));
+        assert_eq!(meta.sha256, Some(written.sha256.clone()));
+        assert!(meta.created.is_some());
+        assert!(meta.blob.is_none());
+
+        let object = storage_file.open(None).await.unwrap();
+        let content: Vec<Bytes> = object.body.try_collect().await.unwrap();
+        assert_eq!(object.size, 19);
+        assert_eq!(content.concat(), b"This is a testfile!");
+
+        let object = storage_file.open(Some(5..9)).await.unwrap();
+        let content: Vec<Bytes> = object.body.try_collect().await.unwrap();
+        assert_eq!(object.size, 4);
+        assert_eq!(content.concat(), b"is a");
+
+        storage_file.delete().await.unwrap();
+        assert!(!storage_file.exists().await);
+        assert!(bucket.open(false).await.is_none());
+        assert!(user_dir.open(false).await.is_none());
+    }
+
+    #[actix_web::test]
+    async fn memory_round_trip() {
+        round_trip(&MemoryBackend::default()).await;
+    }
+
+    #[actix_web::test]
+    async fn filesystem_round_trip() {
+        let root = std::env::temp_dir().join("shares-test-filesystem");
+        round_trip(&FilesystemBackend::new(&root)).await;
+        std::fs::remove_dir(root.join(".partial")).unwrap();
+        std::fs::remove_dir(&root).unwrap();
+    }
+
+    async fn write(user_dir: &UserDir<'_, '_>, content: &'static [u8]) -> Result<(), StatusCode> {
+        let bucket = Bucket::new(user_dir, None).unwrap();
+        let storage_file = StorageFile::new(&bucket, "file.txt".into());
+        let written = storage_file
+            .write(
+                futures::stream::iter(
+                    content
+                        .chunks(4)
+                        .map(|chunk| Ok::<_, actix_web::Error>(Bytes::from_static(chunk))),
+                ),
+                FileMeta::default(),
+            )
+            .await;
+        if written.is_err() {
+            assert!(!storage_file.exists().await);
+            assert!(bucket.open(false).await.is_none());
+        }
+
+        written
+            .map(|_| ())
+            .map_err(|err| err.as_response_error().status_code())
+    }
+
+    #[actix_web::test]
+    async fn limits() {
+        let backend = MemoryBackend::default();
+        let user_data = UserData::default();
+        let user_dir = UserDir::new(&backend, &user_data).with_limits(Limits {
+            max_file_size: Some(19),
+            quota_bytes: Some(30),
+        });
+
+        assert_eq!(
+            write(&user_dir, b"This is a testfile!!").await,
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        assert_eq!(write(&user_dir, b"This is a testfile!").await, Ok(()));
+        assert_eq!(user_dir.usage().await.unwrap(), 19);
+        assert_eq!(
+            write(&user_dir, b"This is a testfile!").await,
+            Err(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        assert_eq!(user_dir.usage().await.unwrap(), 19);
+        assert_eq!(write(&user_dir, b"a testfile!").await, Ok(()));
+        assert_eq!(user_dir.usage().await.unwrap(), 30);
+
+        let bucket = user_dir.buckets().await.unwrap().pop().unwrap();
+        let bucket = Bucket::new(&user_dir, Some(bucket)).unwrap();
+        let storage_file = StorageFile::new(&bucket, "file.txt".into());
+        let size = storage_file.open(None).await.unwrap().size;
+        storage_file.delete().await.unwrap();
+        assert_eq!(user_dir.usage().await.unwrap(), 30 - size);
+
+        // A second server sharing the storage counts the existing files once.
+        let user_data = UserData {
+            usage: Default::default(),
+            ..user_data.clone()
+        };
+        let user_dir = UserDir::new(&backend, &user_data);
+        assert_eq!(user_dir.usage().await.unwrap(), 30 - size);
+    }
+
+    async fn dedup(backend: &dyn StorageBackend) {
+        let user_data = UserData::default();
+        let user_dir = UserDir::new(backend, &user_data).with_dedup(true);
+        let bucket = Bucket::new(&user_dir, None).unwrap();
+        let first = StorageFile::new(&bucket, "first.txt".into());
+        let second = StorageFile::new(&bucket, "second.txt".into());
+        for storage_file in [&first, &second] {
+            let written = write_test_file(storage_file).await;
+            assert_eq!(
+                written.sha256,
+                "2203f2f8e854923946eccbaca2f9ed70ecc6d94fca5f07232a91e488c319bd0b"
+            );
+        }
+        let blob = ".blobs/2203f2f8e854923946eccbaca2f9ed70ecc6d94fca5f07232a91e488c319bd0b";
+        assert_eq!(backend.list(".blobs").await.unwrap().len(), 2);
+        assert_eq!(
+            backend.list(&format!("{}.refs", blob)).await.unwrap().len(),
+            2
+        );
+        assert_eq!(first.content_key().await, blob);
+        assert_eq!(second.metadata().await.unwrap().size, 19);
+        assert_eq!(user_dir.usage().await.unwrap(), 38);
+
+        let written = first
+            .write(
+                futures::stream::iter([Ok::<_, actix_web::Error>(Bytes::from_static(b"other"))]),
+                FileMeta::default(),
+            )
+            .await;
+        assert_eq!(
+            written
+                .err()
+                .map(|err| err.as_response_error().status_code()),
+            Some(StatusCode::CONFLICT)
+        );
+        assert_eq!(backend.list(".blobs").await.unwrap().len(), 2);
+        assert_eq!(user_dir.usage().await.unwrap(), 38);
+
+        first.delete().await.unwrap();
+        assert!(backend.exists(blob).await.unwrap());
+        let object = second.open(None).await.unwrap();
+        let content: Vec<Bytes> = object.body.try_collect().await.unwrap();
+        assert_eq!(content.concat(), b"This is a testfile!");
+
+        second.delete().await.unwrap();
+        assert!(backend.list(".blobs").await.unwrap().is_empty());
+        assert!(user_dir.open(false).await.is_none());
+    }
+
+    #[actix_web::test]
+    async fn memory_dedup() {
+        dedup(&MemoryBackend::default()).await;
+    }
+
+    #[actix_web::test]
+    async fn filesystem_dedup() {
+        let root = std::env::temp_dir().join("shares-test-dedup");
+        dedup(&FilesystemBackend::new(&root)).await;
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn bucket_names() {
+        let backend = MemoryBackend::default();
+        let user_data = UserData::default();
+        let bucket_names = BucketNames {
+            length: Some(1),
+            alphabet: Some("a".into()),
+            ..BucketNames::default()
+        };
+        let user_dir = UserDir::new(&backend, &user_data).with_bucket_names(&bucket_names);
+
+        let bucket = Bucket::random(&user_dir).await.unwrap();
+        assert_eq!(bucket.name, "a");
+        bucket.open(true).await.unwrap();
+        let status = |result: Result<Bucket, actix_web::Error>| {
+            result
+                .err()
+                .map(|err| err.as_response_error().status_code())
+        };
+        assert_eq!(
+            status(Bucket::random(&user_dir).await),
+            Some(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+
+        assert_eq!(
+            status(Bucket::claim(&user_dir, "a".into()).await),
+            Some(StatusCode::CONFLICT)
+        );
+        assert_eq!(
+            status(Bucket::claim(&user_dir, "tus".into()).await),
+            Some(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            status(Bucket::claim(&user_dir, "".into()).await),
+            Some(StatusCode::BAD_REQUEST)
+        );
+        let bucket = Bucket::claim(&user_dir, "b".into()).await.unwrap();
+        assert_eq!(bucket.name, "b");
+        assert!(bucket.open(false).await.is_some());
+
+        let (first, second) = futures::join!(
+            Bucket::claim(&user_dir, "c".into()),
+            Bucket::claim(&user_dir, "c".into())
+        );
+        assert_eq!(
+            [status(first), status(second)],
+            [None, Some(StatusCode::CONFLICT)]
+        );
+    }
+
+    #[actix_web::test]
+    async fn filesystem_claim() {
+        let root = std::env::temp_dir().join("shares-test-claim");
+        let backend = FilesystemBackend::new(&root);
+        let user_data = UserData::default();
+        let user_dir = UserDir::new(&backend, &user_data);
+
+        Bucket::claim(&user_dir, "a".into()).await.unwrap();
+        assert_eq!(
+            Bucket::claim(&user_dir, "a".into())
+                .await
+                .err()
+                .map(|err| err.as_response_error().status_code()),
+            Some(StatusCode::CONFLICT)
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+use std::path::Path;
+
+const EXIF: &[u8] = b"Exif\0\0";
+const XMP: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const XMP_EXTENSION: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+const IPTC: &[u8] = b"Photoshop 3.0\0";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// The EXIF tag of the orientation, which is kept so photos are not shown rotated.
+const ORIENTATION: u16 = 0x0112;
+
+/// Whether metadata can be removed from the file, judged by its extension.
+pub fn is_supported(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            ["jpg", "jpeg", "png", "webp"]
+                .iter()
+                .any(|supported| ext.eq_ignore_ascii_case(supported))
+        })
+        .unwrap_or(false)
+}
+
+/// Removes EXIF, XMP, IPTC and text metadata from a JPEG, PNG or WebP image without touching the pixels.
+///
+/// Returns `None` if there was nothing to remove or the image could not be read.
+pub fn strip(content: &[u8]) -> Option<Vec<u8>> {
+    let stripped = if content.starts_with(&[0xff, 0xd8]) {
+        strip_jpeg(content)
+    } else if content.starts_with(PNG_SIGNATURE) {
+        strip_png(content)
+    } else if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WEBP" {
+        strip_webp(content)
+    } else {
+        None
+    }?;
+
+    (stripped != content).then_some(stripped)
+}
+
+fn u16_be(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn u32_be(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn u32_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn strip_jpeg(content: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = content[..2].to_vec();
+    let mut position = 2;
+    loop {
+        if *content.get(position)? != 0xff {
+            return None;
+        }
+        // Markers may be preceded by any number of fill bytes.
+        let mut marker_position = position + 1;
+        while *content.get(marker_position)? == 0xff {
+            marker_position += 1;
+        }
+        let marker = content[marker_position];
+        match marker {
+            // The scan and everything after it is image data.
+            0xda => {
+                stripped.extend_from_slice(&content[position..]);
+                return Some(stripped);
+            }
+            0xd9 => {
+                stripped.extend_from_slice(&content[position..=marker_position]);
+                return Some(stripped);
+            }
+            0x01 | 0xd0..=0xd7 => {
+                stripped.extend_from_slice(&content[position..=marker_position]);
+                position = marker_position + 1;
+            }
+            _ => {
+                let length =
+                    u16_be(content.get(marker_position + 1..marker_position + 3)?) as usize;
+                let end = marker_position + 1 + length;
+                let data = content.get(marker_position + 3..end)?;
+                let is_metadata = match marker {
+                    0xe1 => {
+                        if data.starts_with(EXIF) {
+                            if let Some(orientation) = jpeg_orientation(&data[EXIF.len()..]) {
+                                stripped.extend_from_slice(&orientation_segment(orientation));
+                            }
+                            true
+                        } else {
+                            data.starts_with(XMP) || data.starts_with(XMP_EXTENSION)
+                        }
+                    }
+                    0xed => data.starts_with(IPTC),
+                    // Comments
+                    0xfe => true,
+                    _ => false,
+                };
+                if !is_metadata {
+                    stripped.extend_from_slice(&content[position..end]);
+                }
+                position = end;
+            }
+        }
+    }
+}
+
+/// The orientation from the first IFD of the TIFF structure in an EXIF segment, if it is not the default.
+fn jpeg_orientation(tiff: &[u8]) -> Option<u16> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = tiff.get(offset..offset + 4)?;
+        Some(if little_endian {
+            u32_le(bytes)
+        } else {
+            u32_be(bytes)
+        })
+    };
+
+    let ifd = read_u32(4)? as usize;
+    let entries = read_u16(ifd)? as usize;
+    (0..entries)
+        .map(|entry| ifd + 2 + entry * 12)
+        .find(|&entry| read_u16(entry) == Some(ORIENTATION))
+        .and_then(|entry| read_u16(entry + 8))
+        .filter(|orientation| (2..=8).contains(orientation))
+}
+
+/// An APP1 segment with an EXIF structure holding only the orientation.
+fn orientation_segment(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    tiff.extend_from_slice(&ORIENTATION.to_be_bytes());
+    // A single SHORT, padded to the four bytes of the value field.
+    tiff.extend_from_slice(&3u16.to_be_bytes());
+    tiff.extend_from_slice(&1u32.to_be_bytes());
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    // No further IFD.
+    tiff.extend_from_slice(&[0, 0, 0, 0]);
+
+    let mut segment = vec![0xff, 0xe1];
+    segment.extend_from_slice(&((2 + EXIF.len() + tiff.len()) as u16).to_be_bytes());
+    segment.extend_from_slice(EXIF);
+    segment.extend_from_slice(&tiff);
+    segment
+}
+
+fn strip_png(content: &[u8]) -> Option<Vec<u8>> {
+    let mut stripped = PNG_SIGNATURE.to_vec();
+    let mut position = PNG_SIGNATURE.len();
+    while position < content.len() {
+        let length = u32_be(content.get(position..position + 4)?) as usize;
+        let kind = content.get(position + 4..position + 8)?;
+        // Length, type, data and CRC
+        let end = position.checked_add(12)?.checked_add(length)?;
+        let chunk = content.get(position..end)?;
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            stripped.extend_from_slice(chunk);
+        }
+        position = end;
+        if kind == b"IEND" {
+            break;
+        }
+    }
+
+    Some(stripped)
+}
+
+fn strip_webp(content: &[u8]) -> Option<Vec<u8>> {
+    let mut chunks = Vec::new();
+    let mut position = 12;
+    while position < content.len() {
+        let kind = content.get(position..position + 4)?;
+        let length = u32_le(content.get(position + 4..position + 8)?) as usize;
+        // Chunks are padded to an even length.
+        let end = (position
+            .checked_add(8)?
+            .checked_add(length)?
+            .checked_add(1)?
+            & !1)
+            .min(content.len());
+        let chunk = content.get(position..end)?;
+        match kind {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" if chunk.len() > 8 => {
+                let mut chunk = chunk.to_vec();
+                // Clear the flags announcing EXIF and XMP metadata.
+                chunk[8] &= !0b1100;
+                chunks.push(chunk);
+            }
+            _ => chunks.push(chunk.to_vec()),
+        }
+        position = end;
+    }
+
+    let chunks = chunks.concat();
+    let mut stripped = b"RIFF".to_vec();
+    stripped.extend_from_slice(&((4 + chunks.len()) as u32).to_le_bytes());
+    stripped.extend_from_slice(b"WEBP");
+    stripped.extend_from_slice(&chunks);
+    Some(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::strip::{is_supported, jpeg_orientation, strip, EXIF};
+    use image::{ImageFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xff, marker];
+        segment.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(data);
+        segment
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_strip_jpeg() {
+        let mut image = Vec::new();
+        RgbImage::from_pixel(16, 16, Rgb([200, 100, 50]))
+            .write_to(&mut Cursor::new(&mut image), ImageFormat::Jpeg)
+            .unwrap();
+
+        // Little endian EXIF with the orientation and a GPS IFD pointer in the first IFD.
+        let mut exif = EXIF.to_vec();
+        exif.extend_from_slice(b"II\x2a\0\x08\0\0\0");
+        exif.extend_from_slice(&2u16.to_le_bytes());
+        exif.extend_from_slice(&[0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0]);
+        exif.extend_from_slice(&[0x25, 0x88, 4, 0, 1, 0, 0, 0, 0x26, 0, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        exif.extend_from_slice(b"GPS secret");
+        let mut with_metadata = image[..2].to_vec();
+        with_metadata.extend_from_slice(&segment(0xe1, &exif));
+        with_metadata.extend_from_slice(&segment(
+            0xe1,
+            b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>",
+        ));
+        with_metadata.extend_from_slice(&segment(0xfe, b"A comment"));
+        with_metadata.extend_from_slice(&image[2..]);
+
+        let stripped = strip(&with_metadata).unwrap();
+        assert!(!contains(&stripped, b"GPS secret"));
+        assert!(!contains(&stripped, b"xmpmeta"));
+        assert!(!contains(&stripped, b"A comment"));
+        let exif_start = stripped
+            .windows(EXIF.len())
+            .position(|window| window == EXIF)
+            .unwrap();
+        assert_eq!(
+            jpeg_orientation(&stripped[exif_start + EXIF.len()..]),
+            Some(6)
+        );
+        assert!(image::load_from_memory(&stripped).is_ok());
+
+        assert_eq!(strip(&image), None);
+    }
+
+    #[test]
+    fn test_strip_png() {
+        let mut image = Vec::new();
+        RgbImage::from_pixel(16, 16, Rgb([200, 100, 50]))
+            .write_to(&mut Cursor::new(&mut image), ImageFormat::Png)
+            .unwrap();
+        // A tEXt chunk right after the IHDR chunk, the CRC is not checked when stripping.
+        let mut with_metadata = image[..33].to_vec();
+        with_metadata.extend_from_slice(&12u32.to_be_bytes());
+        with_metadata.extend_from_slice(b"tEXtAuthor\0Alice");
+        with_metadata.extend_from_slice(&[0, 0, 0, 0]);
+        with_metadata.extend_from_slice(&image[33..]);
+
+        let stripped = strip(&with_metadata).unwrap();
+        assert_eq!(stripped, image);
+        assert_eq!(strip(&image), None);
+    }
+
+    #[test]
+    fn test_strip_webp() {
+        let mut chunks = Vec::new();
+        chunks.extend_from_slice(b"VP8X\x0a\0\0\0");
+        chunks.extend_from_slice(&[0b1100, 0, 0, 0, 15, 0, 0, 15, 0, 0]);
+        chunks.extend_from_slice(b"VP8L\x03\0\0\0abc\0");
+        chunks.extend_from_slice(b"EXIF\x05\0\0\0GPS!!\0");
+        chunks.extend_from_slice(b"XMP \x04\0\0\0<x/>");
+        let mut with_metadata = b"RIFF".to_vec();
+        with_metadata.extend_from_slice(&((4 + chunks.len()) as u32).to_le_bytes());
+        with_metadata.extend_from_slice(b"WEBP");
+        with_metadata.extend_from_slice(&chunks);
+
+        let stripped = strip(&with_metadata).unwrap();
+        assert!(!contains(&stripped, b"GPS"));
+        assert!(!contains(&stripped, b"<x/>"));
+        assert_eq!(stripped[20], 0);
+        assert_eq!(
+            u32::from_le_bytes(stripped[4..8].try_into().unwrap()) as usize,
+            stripped.len() - 8
+        );
+        assert!(contains(&stripped, b"VP8L\x03\0\0\0abc\0"));
+    }
+
+    #[test]
+    fn test_is_supported() {
+        assert!(is_supported("photo.JPG"));
+        assert!(is_supported("image.webp"));
+        assert!(!is_supported("animation.gif"));
+        assert!(strip(b"no image").is_none());
+        assert!(strip(&[0xff, 0xd8, 0xff]).is_none());
+    }
+}
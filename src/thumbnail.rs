@@ -0,0 +1,115 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageReader, ImageResult, Limits};
+use std::io::Cursor;
+use std::path::Path;
+
+const JPEG_QUALITY: u8 = 85;
+/// Images may be this many times wider and higher than the thumbnail, e.g. 8192 pixels for the default 256.
+const MAX_SOURCE_SCALE: u32 = 32;
+/// Bytes the decoder may allocate, enough for a 40 megapixel photo.
+const MAX_DECODE_ALLOC: u64 = 128 * 1024 * 1024;
+
+/// Whether thumbnails can be generated for the file, judged by its extension.
+pub fn is_supported(filename: &str) -> bool {
+    matches!(
+        Path::new(filename)
+            .extension()
+            .and_then(ImageFormat::from_extension),
+        Some(ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP)
+    )
+}
+
+/// Scales the image down to fit into `max_width` x `max_height`, keeping its aspect ratio.
+///
+/// Images with transparency become a PNG, all others a JPEG. Images too large to decode cheaply are refused.
+pub fn generate(content: &[u8], max_width: u32, max_height: u32) -> ImageResult<Vec<u8>> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(max_width.saturating_mul(MAX_SOURCE_SCALE));
+    limits.max_image_height = Some(max_height.saturating_mul(MAX_SOURCE_SCALE));
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = ImageReader::new(Cursor::new(content)).with_guessed_format()?;
+    reader.limits(limits);
+    let image = reader.decode()?;
+    let (width, height) = image.dimensions();
+    let thumbnail = if width > max_width || height > max_height {
+        image.thumbnail(max_width, max_height)
+    } else {
+        image
+    };
+
+    let mut thumbnail_content = Vec::new();
+    if thumbnail.color().has_alpha() {
+        thumbnail.write_to(&mut Cursor::new(&mut thumbnail_content), ImageFormat::Png)?;
+    } else {
+        let rgb = DynamicImage::ImageRgb8(thumbnail.to_rgb8());
+        rgb.write_with_encoder(JpegEncoder::new_with_quality(
+            &mut thumbnail_content,
+            JPEG_QUALITY,
+        ))?;
+    }
+
+    Ok(thumbnail_content)
+}
+
+/// The content type of a thumbnail made by `generate`.
+pub fn content_type(thumbnail: &[u8]) -> mime::Mime {
+    match image::guess_format(thumbnail) {
+        Ok(ImageFormat::Png) => mime::IMAGE_PNG,
+        _ => mime::IMAGE_JPEG,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::thumbnail::{content_type, generate, is_supported};
+    use image::{GenericImageView, ImageFormat, Rgb, RgbImage, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    fn encode(image: impl Into<image::DynamicImage>, format: ImageFormat) -> Vec<u8> {
+        let mut content = Vec::new();
+        image
+            .into()
+            .write_to(&mut Cursor::new(&mut content), format)
+            .unwrap();
+        content
+    }
+
+    #[test]
+    fn test_generate() {
+        let photo = encode(
+            RgbImage::from_pixel(1000, 500, Rgb([200, 100, 50])),
+            ImageFormat::Png,
+        );
+        let thumbnail = generate(&photo, 200, 200).unwrap();
+        assert_eq!(content_type(&thumbnail), mime::IMAGE_JPEG);
+        let image = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!(image.dimensions(), (200, 100));
+
+        let icon = encode(
+            RgbaImage::from_pixel(50, 80, Rgba([0, 0, 0, 0])),
+            ImageFormat::Png,
+        );
+        let thumbnail = generate(&icon, 200, 200).unwrap();
+        assert_eq!(content_type(&thumbnail), mime::IMAGE_PNG);
+        let image = image::load_from_memory(&thumbnail).unwrap();
+        assert_eq!(image.dimensions(), (50, 80));
+
+        assert!(generate(b"no image", 200, 200).is_err());
+
+        // Tiny to send, but huge to decode.
+        let huge = encode(RgbImage::new(7000, 1), ImageFormat::Png);
+        assert!(generate(&huge, 200, 200).is_err());
+        assert!(generate(&huge, 256, 256).is_ok());
+    }
+
+    #[test]
+    fn test_is_supported() {
+        assert!(is_supported("image.png"));
+        assert!(is_supported("photo.JPG"));
+        assert!(is_supported("animation.gif"));
+        assert!(is_supported("image.webp"));
+        assert!(!is_supported("image.svg"));
+        assert!(!is_supported("notes.txt"));
+        assert!(!is_supported("link"));
+    }
+}
@@ -0,0 +1,56 @@
+use ring::hmac;
+
+const ALPHABET: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// The upload is identified by its creation time and content as well, so the token does not
+/// delete a later upload to the same path.
+fn message(path: &str, created: u64, sha256: &str) -> String {
+    format!("delete:{}:{}:{}", path, created, sha256)
+}
+
+/// Token allowing to delete the file at `path` (`user/bucket/filename`) without the user's key,
+/// `created` and `sha256` are those recorded in its `FileMeta`.
+pub fn deletion_token(secret: &str, path: &str, created: u64, sha256: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = hmac::sign(&key, message(path, created, sha256).as_bytes());
+
+    base32::encode(ALPHABET, tag.as_ref()).to_lowercase()
+}
+
+pub fn verify_deletion_token(
+    secret: &str,
+    path: &str,
+    created: u64,
+    sha256: &str,
+    token: &str,
+) -> bool {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+    match base32::decode(ALPHABET, &token.to_uppercase()) {
+        Some(tag) => hmac::verify(&key, message(path, created, sha256).as_bytes(), &tag).is_ok(),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token::{deletion_token, verify_deletion_token};
+
+    #[test]
+    fn test_deletion_token() {
+        let path = "user1/bucket/file.txt";
+        let token = deletion_token("secret", path, 100, "abc");
+        assert_eq!(token.len(), 52);
+        assert!(verify_deletion_token("secret", path, 100, "abc", &token));
+        assert!(!verify_deletion_token(
+            "secret",
+            "user1/bucket/other.txt",
+            100,
+            "abc",
+            &token
+        ));
+        assert!(!verify_deletion_token("secret", path, 101, "abc", &token));
+        assert!(!verify_deletion_token("secret", path, 100, "abd", &token));
+        assert!(!verify_deletion_token("other", path, 100, "abc", &token));
+        assert!(!verify_deletion_token("secret", path, 100, "abc", "!"));
+    }
+}
@@ -0,0 +1,487 @@
+use crate::backend::StorageBackend;
+use actix_web::error::{ErrorBadRequest, ErrorInternalServerError};
+use actix_web::web::Bytes;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures::{Stream, StreamExt, TryStreamExt};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+/// In progress uploads are kept as `.tus/<id>/info.json` and one file per `PATCH`, named after its offset.
+const TUS_DIR: &str = ".tus";
+const INFO: &str = "info.json";
+const ID_LEN: usize = 32;
+
+/// Uploads a `PATCH` is currently appending to.
+static APPENDING: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+/// The state of a resumable upload, kept until it expires.
+#[derive(Deserialize, Serialize, Clone, PartialEq, Debug)]
+pub struct TusInfo {
+    pub username: String,
+    /// The folder of the user, whose quota counts the whole length until the upload is complete.
+    #[serde(default)]
+    pub folder: String,
+    /// The size of the whole upload in bytes.
+    pub length: u64,
+    /// The decoded `Upload-Metadata`, e.g. `filename` and `filetype`.
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Unix timestamp after which an unfinished upload is deleted.
+    pub expires: u64,
+    /// The bucket the complete upload is moved into, recorded before it is, so a retry finishes into the same file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    /// The url of the file, once the upload is complete.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletion_url: Option<String>,
+    /// Whether metadata like EXIF was removed from the completed file.
+    #[serde(default)]
+    pub metadata_stripped: bool,
+}
+
+impl TusInfo {
+    /// All bytes were received, the upload is being or was moved into a bucket.
+    pub fn is_complete(&self) -> bool {
+        self.bucket.is_some() || self.url.is_some()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            <= SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+    }
+}
+
+/// Parses an `Upload-Metadata` header, comma separated keys each followed by an optional base64 value.
+pub fn parse_metadata(header: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => (key, BASE64.decode(value.trim()).ok()?),
+            None => (pair, Vec::new()),
+        };
+        metadata.insert(key.to_string(), String::from_utf8(value).ok()?);
+    }
+
+    Some(metadata)
+}
+
+/// Formats the metadata as an `Upload-Metadata` header again.
+pub fn format_metadata(metadata: &HashMap<String, String>) -> String {
+    let mut pairs: Vec<String> = metadata
+        .iter()
+        .map(|(key, value)| match value.is_empty() {
+            true => key.to_string(),
+            false => format!("{} {}", key, BASE64.encode(value)),
+        })
+        .collect();
+    pairs.sort();
+    pairs.join(",")
+}
+
+/// Marks an upload as being appended to until dropped, so two requests never append at once.
+pub struct AppendGuard(String);
+
+impl Drop for AppendGuard {
+    fn drop(&mut self) {
+        if let Some(appending) = APPENDING.lock().unwrap().as_mut() {
+            appending.remove(&self.0);
+        }
+    }
+}
+
+pub struct TusUpload<'a> {
+    backend: &'a dyn StorageBackend,
+    pub id: String,
+}
+
+impl<'a> TusUpload<'a> {
+    /// Validates an id from a request, to never leave the upload directory.
+    pub fn new(backend: &'a dyn StorageBackend, id: Option<String>) -> Option<Self> {
+        let id = id.unwrap_or_else(|| {
+            rand::thread_rng()
+                .sample_iter(rand::distributions::Alphanumeric)
+                .take(ID_LEN)
+                .map(char::from)
+                .collect()
+        });
+        if id.len() == ID_LEN && id.chars().all(|char| char.is_ascii_alphanumeric()) {
+            Some(TusUpload { backend, id })
+        } else {
+            None
+        }
+    }
+
+    fn key(&self) -> String {
+        format!("{}/{}", TUS_DIR, self.id)
+    }
+
+    fn info_key(&self) -> String {
+        format!("{}/{}", self.key(), INFO)
+    }
+
+    /// The chunks written so far, ordered by their offset.
+    async fn parts(&self) -> io::Result<Vec<(u64, String)>> {
+        let mut parts: Vec<(u64, String)> = self
+            .backend
+            .list(&self.key())
+            .await?
+            .into_iter()
+            .filter_map(|name| {
+                let offset = name.parse().ok()?;
+                Some((offset, format!("{}/{}", self.key(), name)))
+            })
+            .collect();
+        parts.sort();
+        Ok(parts)
+    }
+
+    pub async fn create(&self, info: &TusInfo) -> io::Result<()> {
+        self.backend.create_dir(TUS_DIR).await?;
+        self.backend.create_dir(&self.key()).await?;
+        self.write_info(info).await
+    }
+
+    pub async fn info(&self) -> Option<TusInfo> {
+        let object = self.backend.open(&self.info_key(), None).await.ok()?;
+        let content: Vec<Bytes> = object.body.try_collect().await.ok()?;
+        serde_json::from_slice(&content.concat()).ok()
+    }
+
+    pub async fn write_info(&self, info: &TusInfo) -> io::Result<()> {
+        let key = self.info_key();
+        if self.backend.exists(&key).await? {
+            self.backend.delete(&key).await?;
+        }
+
+        let mut writer = self.backend.create(&key).await?;
+        writer.write(Bytes::from(serde_json::to_vec(info)?)).await?;
+        writer.finish().await
+    }
+
+    /// The number of bytes received, the end of the chunks without a gap.
+    pub async fn offset(&self, info: &TusInfo) -> io::Result<u64> {
+        if info.is_complete() {
+            return Ok(info.length);
+        }
+
+        let mut offset = 0;
+        for (start, key) in self.parts().await? {
+            if start != offset {
+                break;
+            }
+            offset += self.backend.metadata(&key).await?.size;
+        }
+
+        Ok(offset)
+    }
+
+    /// Locks the upload for appending, `None` if another request is appending to it.
+    pub fn lock(&self) -> Option<AppendGuard> {
+        let mut appending = APPENDING.lock().unwrap();
+        if appending
+            .get_or_insert_with(HashSet::new)
+            .insert(self.id.clone())
+        {
+            Some(AppendGuard(self.id.clone()))
+        } else {
+            None
+        }
+    }
+
+    /// Appends the body at `offset`, keeping what was received even if the body breaks off.
+    /// Returns the new offset, fails with 400 if the upload would grow beyond `length`.
+    pub async fn append<S, E>(
+        &self,
+        _guard: &AppendGuard,
+        offset: u64,
+        length: u64,
+        mut stream: S,
+    ) -> Result<u64, actix_web::Error>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: Into<actix_web::Error>,
+    {
+        // Backends which can append keep everything in one part, which can be moved into a bucket as a whole.
+        let last = self.parts().await?.pop().map(|(_, key)| key);
+        let part = format!("{}/{:020}", self.key(), offset);
+        let mut writer = match self.backend.append(last.as_ref().unwrap_or(&part)).await {
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                self.backend.create(&part).await?
+            }
+            writer => writer?,
+        };
+        let mut received = 0;
+        let mut result = Ok(());
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) if offset + received + chunk.len() as u64 > length => {
+                    // The chunk is not written, a new part is discarded as a whole by dropping its writer.
+                    return Err(ErrorBadRequest("Upload-Length exceeded"));
+                }
+                Ok(chunk) => {
+                    received += chunk.len() as u64;
+                    writer.write(chunk).await?;
+                }
+                Err(err) => {
+                    result = Err(err.into());
+                    break;
+                }
+            }
+        }
+
+        if received > 0 {
+            writer.finish().await?;
+        }
+        result.map(|_| offset + received)
+    }
+
+    /// The content of a complete upload.
+    pub async fn content(&self) -> io::Result<impl Stream<Item = io::Result<Bytes>> + Unpin + '_> {
+        let parts = self.parts().await?;
+        Ok(Box::pin(
+            futures::stream::iter(parts)
+                .then(move |(_, key)| async move {
+                    self.backend
+                        .open(&key, None)
+                        .await
+                        .map(|object| object.body)
+                })
+                .try_flatten(),
+        ))
+    }
+
+    /// The key of the only part, if the content was received in one, so it can be moved instead of copied.
+    pub async fn single_part(&self) -> io::Result<Option<String>> {
+        let mut parts = self.parts().await?;
+        Ok(match parts.len() {
+            1 => parts.pop().map(|(_, key)| key),
+            _ => None,
+        })
+    }
+
+    /// Removes the chunks, only keeping the info of a complete upload.
+    pub async fn delete_parts(&self) -> io::Result<()> {
+        for (_, key) in self.parts().await? {
+            self.backend.delete(&key).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete(&self) -> Result<(), actix_web::Error> {
+        self.delete_parts().await?;
+        if self.backend.exists(&self.info_key()).await? {
+            self.backend.delete(&self.info_key()).await?;
+        }
+        self.backend
+            .remove_dir(&self.key())
+            .await
+            .map_err(|_| ErrorInternalServerError("Upload can not be deleted"))
+    }
+}
+
+/// The length of all unfinished uploads into `folder`, which is reserved in its quota.
+pub async fn reserved(backend: &dyn StorageBackend, folder: &str) -> io::Result<u64> {
+    if !backend.exists(TUS_DIR).await? {
+        return Ok(0);
+    }
+
+    let mut reserved = 0;
+    for id in backend.list(TUS_DIR).await? {
+        let upload = match TusUpload::new(backend, Some(id)) {
+            Some(upload) => upload,
+            None => continue,
+        };
+        match upload.info().await {
+            Some(info) if info.folder == folder && info.url.is_none() => reserved += info.length,
+            _ => {}
+        }
+    }
+
+    Ok(reserved)
+}
+
+/// Deletes all expired uploads, complete or not, and returns the info of the deleted ones.
+pub async fn reap(backend: &dyn StorageBackend) -> Vec<TusInfo> {
+    let mut reaped = Vec::new();
+    let ids = match backend.exists(TUS_DIR).await {
+        Ok(true) => backend.list(TUS_DIR).await.unwrap_or_default(),
+        _ => return reaped,
+    };
+    for id in ids {
+        let upload = match TusUpload::new(backend, Some(id)) {
+            Some(upload) => upload,
+            None => continue,
+        };
+        let info = match upload.info().await {
+            Some(info) if info.is_expired() => info,
+            _ => continue,
+        };
+        println!("Reaping expired Upload: {}", upload.id);
+        match upload.delete().await {
+            Ok(()) => reaped.push(info),
+            Err(err) => println!("Could not reap Upload: {}", err),
+        }
+    }
+
+    reaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::{FilesystemBackend, MemoryBackend};
+    use crate::tus::{format_metadata, parse_metadata, reap, reserved, TusInfo, TusUpload};
+    use actix_web::web::Bytes;
+    use futures::TryStreamExt;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata =
+            parse_metadata("filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,is_confidential")
+                .unwrap();
+        assert_eq!(metadata["filename"], "world_domination_plan.pdf");
+        assert_eq!(metadata["is_confidential"], "");
+        assert_eq!(parse_metadata("").unwrap(), HashMap::new());
+        assert!(parse_metadata("filename !!!").is_none());
+        assert_eq!(
+            format_metadata(&metadata),
+            "filename d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==,is_confidential"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_append() {
+        let backend = MemoryBackend::default();
+        assert!(TusUpload::new(&backend, Some("../etc".into())).is_none());
+        let upload = TusUpload::new(&backend, None).unwrap();
+        let info = TusInfo {
+            username: "user1".into(),
+            folder: "user1".into(),
+            length: 10,
+            metadata: HashMap::new(),
+            expires: u64::MAX,
+            bucket: None,
+            url: None,
+            deletion_url: None,
+            metadata_stripped: false,
+        };
+        upload.create(&info).await.unwrap();
+        assert_eq!(upload.info().await, Some(info.clone()));
+        assert_eq!(reserved(&backend, "user1").await.unwrap(), 10);
+        assert_eq!(reserved(&backend, "user2").await.unwrap(), 0);
+
+        let guard = upload.lock().unwrap();
+        assert!(upload.lock().is_none());
+        let chunks = [
+            Ok(Bytes::from_static(b"0123")),
+            Err(actix_web::error::ErrorBadRequest("gone")),
+        ];
+        assert!(upload
+            .append(&guard, 0, 10, futures::stream::iter(chunks))
+            .await
+            .is_err());
+        assert_eq!(upload.offset(&info).await.unwrap(), 4);
+        assert!(upload
+            .append(
+                &guard,
+                4,
+                10,
+                futures::stream::iter([Ok::<_, actix_web::Error>(Bytes::from_static(b"4567890"))])
+            )
+            .await
+            .is_err());
+        assert_eq!(upload.offset(&info).await.unwrap(), 4);
+        let offset = upload
+            .append(
+                &guard,
+                4,
+                10,
+                futures::stream::iter([Ok::<_, actix_web::Error>(Bytes::from_static(b"456789"))]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(offset, 10);
+        drop(guard);
+        assert!(upload.lock().is_some());
+
+        let content: Vec<Bytes> = upload.content().await.unwrap().try_collect().await.unwrap();
+        assert_eq!(content.concat(), b"0123456789");
+        // Memory can not append, every `PATCH` is a part of its own.
+        assert_eq!(upload.single_part().await.unwrap(), None);
+
+        let complete = TusInfo {
+            url: Some("http://localhost:8080/user1/bucket/file".into()),
+            ..info.clone()
+        };
+        upload.delete_parts().await.unwrap();
+        assert_eq!(upload.offset(&info).await.unwrap(), 0);
+        assert_eq!(upload.offset(&complete).await.unwrap(), 10);
+        upload.write_info(&complete).await.unwrap();
+        assert_eq!(reserved(&backend, "user1").await.unwrap(), 0);
+
+        assert!(reap(&backend).await.is_empty());
+        assert!(upload.info().await.is_some());
+        upload
+            .write_info(&TusInfo {
+                expires: 0,
+                ..info.clone()
+            })
+            .await
+            .unwrap();
+        assert_eq!(reap(&backend).await, vec![TusInfo { expires: 0, ..info }]);
+        assert!(upload.info().await.is_none());
+    }
+
+    #[actix_web::test]
+    async fn filesystem_append() {
+        let root = std::env::temp_dir().join("shares-test-tus");
+        let backend = FilesystemBackend::new(&root);
+        let upload = TusUpload::new(&backend, None).unwrap();
+        let info = TusInfo {
+            username: "user1".into(),
+            folder: "user1".into(),
+            length: 10,
+            metadata: HashMap::new(),
+            expires: u64::MAX,
+            bucket: None,
+            url: None,
+            deletion_url: None,
+            metadata_stripped: false,
+        };
+        upload.create(&info).await.unwrap();
+
+        let guard = upload.lock().unwrap();
+        for (offset, chunk) in [(0, b"0123"), (4, b"4567")] {
+            let chunks = [Ok::<_, actix_web::Error>(Bytes::from_static(chunk))];
+            upload
+                .append(&guard, offset, 10, futures::stream::iter(chunks))
+                .await
+                .unwrap();
+        }
+        assert_eq!(upload.offset(&info).await.unwrap(), 8);
+
+        // Everything is appended to one part, which can be moved as a whole.
+        let part = upload.single_part().await.unwrap().unwrap();
+        assert_eq!(std::fs::read(root.join(part)).unwrap(), b"01234567");
+
+        upload.delete().await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}